@@ -0,0 +1,528 @@
+use crate::vars::{is_valid_name, Variables};
+
+// How deeply variables whose values are themselves expressions may refer
+// to one another before giving up, so `a=a` can't recurse forever.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// Every operator, longest first so e.g. `<<=` isn't read as `<<` and `=`.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=",
+    "%=", "&=", "^=", "|=", "++", "--", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", "(", ")", ",",
+];
+
+// The binary operators and how tightly they bind; `**` is the only one
+// that groups right to left.
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+// Parses an integer constant: decimal, hex with `0x`, octal with a leading
+// `0`, or `base#digits` for any base from 2 to 64 (digits, then lower and
+// upper case letters, then `@` and `_`).
+fn parse_number(text: &str) -> Option<i64> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        (
+            base.parse::<u32>().ok().filter(|b| (2..=64).contains(b))?,
+            digits,
+        )
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return None,
+        };
+        if digit >= base {
+            return None;
+        }
+        value = value
+            .wrapping_mul(i64::from(base))
+            .wrapping_add(i64::from(digit));
+    }
+    Some(value)
+}
+
+// Splits an expression into numbers, names and operators. `++` and `--`
+// are only taken as increments next to a name (`x++`, `--x`); elsewhere
+// they're two signs, so `5--3` is 5 minus -3.
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_#@".contains(chars[i]))
+            {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                let value = parse_number(&word)
+                    .ok_or_else(|| format!("{word}: value too great for base"))?;
+                tokens.push(Token::Number(value));
+            } else if is_valid_name(&word) {
+                tokens.push(Token::Name(word));
+            } else {
+                return Err(format!("{expr}: syntax error: invalid arithmetic operator"));
+            }
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            let Some(&op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) else {
+                return Err(format!(
+                    "{expr}: syntax error: invalid arithmetic operator (error token is \"{rest}\")"
+                ));
+            };
+            let next_is_name = chars
+                .get(i + 2)
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_');
+            let after_name = matches!(tokens.last(), Some(Token::Name(_)));
+            let op = match op {
+                "++" | "--" if !after_name && !next_is_name => &op[..1],
+                op => op,
+            };
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+// A recursive-descent evaluator over the tokens of one expression, working
+// out the value as it parses. While `skipping` (the side of `&&`, `||` or
+// `?:` that isn't taken), everything is still parsed but nothing is
+// assigned and nothing can fail, e.g. `0 && 1/0` is fine.
+struct Evaluator<'a> {
+    expr: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut Variables,
+    depth: usize,
+    skipping: bool,
+}
+
+impl Evaluator<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn syntax_error(&self) -> String {
+        match self.tokens.get(self.pos) {
+            None => format!("{}: syntax error: operand expected", self.expr),
+            Some(_) => format!("{}: syntax error in expression", self.expr),
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() != Some(op) {
+            return Err(self.syntax_error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // The value of a variable: 0 if it's unset or empty, otherwise its
+    // value evaluated as an expression in its own right.
+    fn variable(&mut self, name: &str) -> Result<i64, String> {
+        let value = self.vars.get(name).unwrap_or_default().trim().to_string();
+        if value.is_empty() || self.skipping {
+            return Ok(0);
+        }
+        if let Some(n) = parse_number(&value) {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("{name}: expression recursion level exceeded"));
+        }
+        evaluate_at_depth(&value, self.vars, self.depth + 1)
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<i64, String> {
+        if !self.skipping {
+            self.vars.set(name, &value.to_string())?;
+        }
+        Ok(value)
+    }
+
+    fn apply(&self, op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+        if self.skipping {
+            return Ok(0);
+        }
+        Ok(match op {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err(format!("{}: division by 0", self.expr)),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "**" => {
+                if rhs < 0 {
+                    return Err(format!("{}: exponent less than 0", self.expr));
+                }
+                lhs.wrapping_pow(u32::try_from(rhs).unwrap_or(u32::MAX))
+            }
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "<" => i64::from(lhs < rhs),
+            ">" => i64::from(lhs > rhs),
+            "<=" => i64::from(lhs <= rhs),
+            ">=" => i64::from(lhs >= rhs),
+            "==" => i64::from(lhs == rhs),
+            "!=" => i64::from(lhs != rhs),
+            "&" => lhs & rhs,
+            "^" => lhs ^ rhs,
+            "|" => lhs | rhs,
+            _ => unreachable!("{op} is not a binary operator"),
+        })
+    }
+
+    // Evaluates the tokens from `pos` with `skipping` set if `skip` is.
+    fn maybe_skipping<T>(
+        &mut self,
+        skip: bool,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let was_skipping = self.skipping;
+        self.skipping |= skip;
+        let result = parse(self);
+        self.skipping = was_skipping;
+        result
+    }
+
+    // expr: assignment (`,` assignment)*
+    fn comma(&mut self) -> Result<i64, String> {
+        let mut value = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    // `name = value` and the compound forms like `name += value`, grouping
+    // right to left; anything else is a conditional expression.
+    fn assignment(&mut self) -> Result<i64, String> {
+        let target = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Name(name)), Some(Token::Op(op))) if op.ends_with('=') => {
+                let binary = &op[..op.len() - 1];
+                match *op {
+                    "=" => Some((name.clone(), None)),
+                    "==" | "!=" | "<=" | ">=" => None,
+                    _ => Some((name.clone(), Some(binary))),
+                }
+            }
+            _ => None,
+        };
+        let Some((name, binary)) = target else {
+            return self.conditional();
+        };
+        self.pos += 2;
+        let rhs = self.assignment()?;
+        let value = match binary {
+            Some(op) => {
+                let current = self.variable(&name)?;
+                self.apply(op, current, rhs)?
+            }
+            None => rhs,
+        };
+        self.assign(&name, value)
+    }
+
+    // cond ? a : b, evaluating only the side that's chosen.
+    fn conditional(&mut self) -> Result<i64, String> {
+        let condition = self.binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let yes = self.maybe_skipping(condition == 0, Self::comma)?;
+        self.expect(":")?;
+        let no = self.maybe_skipping(condition != 0, Self::conditional)?;
+        Ok(if condition != 0 { yes } else { no })
+    }
+
+    // Precedence climbing over the binary operators, starting from those
+    // that bind at least as tightly as `min_precedence`.
+    fn binary(&mut self, min_precedence: u8) -> Result<i64, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek_op() {
+            let Some(precedence) = binary_precedence(op).filter(|&p| p >= min_precedence) else {
+                break;
+            };
+            self.pos += 1;
+            let next = if op == "**" {
+                precedence
+            } else {
+                precedence + 1
+            };
+            lhs = match op {
+                "&&" => {
+                    let rhs = self.maybe_skipping(lhs == 0, |e| e.binary(next))?;
+                    i64::from(lhs != 0 && rhs != 0)
+                }
+                "||" => {
+                    let rhs = self.maybe_skipping(lhs != 0, |e| e.binary(next))?;
+                    i64::from(lhs != 0 || rhs != 0)
+                }
+                _ => {
+                    let rhs = self.binary(next)?;
+                    self.apply(op, lhs, rhs)?
+                }
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let Some(op) = self.peek_op() else {
+            return self.postfix();
+        };
+        match op {
+            "++" | "--" => {
+                self.pos += 1;
+                let Some(Token::Name(name)) = self.tokens.get(self.pos).cloned() else {
+                    return Err(self.syntax_error());
+                };
+                self.pos += 1;
+                let current = self.variable(&name)?;
+                let value = if op == "++" {
+                    current.wrapping_add(1)
+                } else {
+                    current.wrapping_sub(1)
+                };
+                self.assign(&name, value)
+            }
+            "-" | "+" | "!" | "~" => {
+                self.pos += 1;
+                // As in bash, a sign binds tighter than `**`: -2**2 is 4.
+                let operand = self.unary()?;
+                Ok(match op {
+                    "-" => operand.wrapping_neg(),
+                    "+" => operand,
+                    "!" => i64::from(operand == 0),
+                    _ => !operand,
+                })
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    // A number, a variable (perhaps followed by `++` or `--`) or a
+    // parenthesised expression.
+    fn postfix(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Name(name)) => {
+                let value = self.variable(&name)?;
+                match self.peek_op() {
+                    Some(op @ ("++" | "--")) => {
+                        self.pos += 1;
+                        let updated = if op == "++" {
+                            value.wrapping_add(1)
+                        } else {
+                            value.wrapping_sub(1)
+                        };
+                        self.assign(&name, updated)?;
+                        Ok(value)
+                    }
+                    _ => Ok(value),
+                }
+            }
+            Some(Token::Op("(")) => {
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.syntax_error())
+            }
+        }
+    }
+}
+
+fn evaluate_at_depth(expr: &str, vars: &mut Variables, depth: usize) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut evaluator = Evaluator {
+        expr,
+        tokens,
+        pos: 0,
+        vars,
+        depth,
+        skipping: false,
+    };
+    let value = evaluator.comma()?;
+    if evaluator.pos < evaluator.tokens.len() {
+        return Err(evaluator.syntax_error());
+    }
+    Ok(value)
+}
+
+// Evaluates an arithmetic expression as in `$((...))` or `((...))`, with
+// C's integer operators and precedence (plus `**` for powers). Variables
+// can be read by name and assigned with `=`, `+=` and the like or `++`
+// and `--`. Arithmetic is on 64-bit integers and wraps on overflow; an
+// empty expression is 0. Errors for a syntax error, division by zero, a
+// negative exponent or an assignment to a readonly variable.
+pub fn evaluate(expr: &str, vars: &mut Variables) -> Result<i64, String> {
+    evaluate_at_depth(expr, vars, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> Result<i64, String> {
+        evaluate(expr, &mut Variables::default())
+    }
+
+    #[test]
+    fn evaluate_respects_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("-(2 ** 2)"), Ok(-4));
+        assert_eq!(eval("2 ** -1 ** 2"), Ok(2));
+        assert_eq!(eval("- -3"), Ok(3));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("7 - 2 - 1"), Ok(4));
+        assert_eq!(eval("1 < 2 == 1"), Ok(1));
+        assert_eq!(eval("6 & 3 ^ 1"), Ok(3));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn evaluate_unary_and_logical_operators() {
+        assert_eq!(eval("!0 + !5"), Ok(1));
+        assert_eq!(eval("~0"), Ok(-1));
+        assert_eq!(eval("5--3"), Ok(8));
+        assert_eq!(eval("2 && 0 || 3"), Ok(1));
+        assert_eq!(eval("0 ? 10 : 1 ? 20 : 30"), Ok(20));
+        assert_eq!(eval("1, 2, 3"), Ok(3));
+    }
+
+    #[test]
+    fn evaluate_number_bases() {
+        assert_eq!(eval("0x1F + 010"), Ok(39));
+        assert_eq!(eval("2#101 + 36#z"), Ok(40));
+        assert_eq!(eval("64#_"), Ok(63));
+        assert!(eval("09").is_err());
+        assert!(eval("2#102").is_err());
+    }
+
+    #[test]
+    fn evaluate_reports_errors() {
+        assert_eq!(eval("1 / 0"), Err("1 / 0: division by 0".to_string()));
+        assert_eq!(eval("5 % 0"), Err("5 % 0: division by 0".to_string()));
+        assert!(eval("2 ** -1")
+            .unwrap_err()
+            .contains("exponent less than 0"));
+        assert!(eval("1 +").unwrap_err().contains("operand expected"));
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("1 $ 2").is_err());
+        assert!(eval("1 ? 2").is_err());
+    }
+
+    #[test]
+    fn evaluate_short_circuits() {
+        let mut vars = Variables::default();
+        assert_eq!(evaluate("0 && 1 / 0", &mut vars), Ok(0));
+        assert_eq!(evaluate("1 || (x = 5)", &mut vars), Ok(1));
+        assert_eq!(evaluate("1 ? 2 : 1 / 0", &mut vars), Ok(2));
+        assert_eq!(vars.get("x"), None);
+    }
+
+    #[test]
+    fn evaluate_reads_and_assigns_variables() {
+        let mut vars = Variables::default();
+        vars.set("a", "5").unwrap();
+        vars.set("expr", "a * 2").unwrap();
+        vars.set("empty", "").unwrap();
+        assert_eq!(evaluate("a + expr + empty + unset", &mut vars), Ok(15));
+        assert_eq!(evaluate("b = a += 2", &mut vars), Ok(7));
+        assert_eq!(vars.get("a"), Some("7"));
+        assert_eq!(vars.get("b"), Some("7"));
+        assert_eq!(evaluate("a <<= 1, a", &mut vars), Ok(14));
+        assert_eq!(evaluate("a == 14", &mut vars), Ok(1));
+    }
+
+    #[test]
+    fn evaluate_increments_and_decrements() {
+        let mut vars = Variables::default();
+        vars.set("i", "1").unwrap();
+        assert_eq!(evaluate("i++", &mut vars), Ok(1));
+        assert_eq!(evaluate("++i", &mut vars), Ok(3));
+        assert_eq!(evaluate("i-- + --i", &mut vars), Ok(4));
+        assert_eq!(vars.get("i"), Some("1"));
+        assert!(evaluate("i++ ++", &mut vars).is_err());
+    }
+
+    #[test]
+    fn evaluate_refuses_readonly_and_runaway_variables() {
+        let mut vars = Variables::default();
+        vars.set("r", "1").unwrap();
+        vars.set_readonly("r");
+        assert_eq!(
+            evaluate("r = 2", &mut vars),
+            Err("r: readonly variable".to_string())
+        );
+        vars.set("loop", "loop + 1").unwrap();
+        assert!(evaluate("loop", &mut vars)
+            .unwrap_err()
+            .contains("recursion level exceeded"));
+    }
+
+    #[test]
+    fn evaluate_wraps_on_overflow() {
+        assert_eq!(eval("9223372036854775807 + 1"), Ok(i64::MIN));
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1"), Ok(i64::MIN));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::parser::{backtick_end, double_quote_end, matching_brace, substitution_end};
+
+// For each character of a raw word, whether it's outside any quoting: not
+// in quotes, not backslash-escaped, and not inside a `${...}` or a command
+// substitution. Only those
+// braces and commas take part in brace expansion (and only those tildes
+// and colons in tilde expansion).
+pub fn unquoted_mask(chars: &[char]) -> Vec<bool> {
+    let mut mask = vec![true; chars.len()];
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let end = match chars[i] {
+            '\\' => i + 2,
+            '\'' => closing(chars, i + 1, '\'', false),
+            '"' => double_quote_end(chars, i + 1).map_or(chars.len(), |end| end + 1),
+            '`' => backtick_end(chars, i + 1).map_or(chars.len(), |end| end + 1),
+            '$' if chars.get(i + 1) == Some(&'(') => {
+                substitution_end(chars, i + 2).map_or(chars.len(), |end| end + 1)
+            }
+            '$' if chars.get(i + 1) == Some(&'\'') => closing(chars, i + 2, '\'', true),
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                matching_brace(chars, i + 2).map_or(chars.len(), |end| end + 1)
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        i = end.min(chars.len());
+        mask[start..i].iter_mut().for_each(|m| *m = false);
+    }
+    mask
+}
+
+// The index just past the `quote` that closes a quoted span starting at
+// `start`, skipping backslash-escaped characters if `escapes` is set.
+fn closing(chars: &[char], start: usize, quote: char, escapes: bool) -> usize {
+    let mut i = start;
+    while i < chars.len() && chars[i] != quote {
+        i += if escapes && chars[i] == '\\' { 2 } else { 1 };
+    }
+    i + 1
+}
+
+// Expands a `{start..end}` or `{start..end..step}` sequence of integers or
+// single letters. Integers are zero-padded to the width of the wider
+// endpoint if either one has a leading zero, as in `{01..12}`. Returns
+// None if `body` isn't a valid sequence.
+fn sequence(body: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = body.split("..").collect();
+    let (start, end) = match parts.as_slice() {
+        [start, end] | [start, end, _] => (*start, *end),
+        _ => return None,
+    };
+    let step = match parts.get(2) {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+
+    let as_letter = |s: &str| {
+        let mut chars = s.chars();
+        chars
+            .next()
+            .filter(|c| c.is_ascii_alphabetic() && chars.next().is_none())
+    };
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| {
+            let digits = s.trim_start_matches(['-', '+']);
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        Some(
+            stepped(first, last, step)
+                .into_iter()
+                .map(|n| format!("{n:0width$}"))
+                .collect(),
+        )
+    } else if let (Some(first), Some(last)) = (as_letter(start), as_letter(end)) {
+        let items = stepped(
+            i64::from(u32::from(first)),
+            i64::from(u32::from(last)),
+            step,
+        );
+        Some(
+            items
+                .into_iter()
+                .filter_map(|n| u32::try_from(n).ok().and_then(char::from_u32))
+                .map(String::from)
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+// Every `step`th value from `first` to `last` inclusive, counting down if
+// `last` is smaller.
+fn stepped(first: i64, last: i64, step: u64) -> Vec<i64> {
+    let step = i64::try_from(step).unwrap_or(i64::MAX);
+    let mut values = Vec::new();
+    let mut n = first;
+    if first <= last {
+        while n <= last {
+            values.push(n);
+            n = match n.checked_add(step) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+    } else {
+        while n >= last {
+            values.push(n);
+            n = match n.checked_sub(step) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+    }
+    values
+}
+
+// Finds the first brace expression in `chars` that actually expands, and
+// returns where it starts and ends along with the alternatives it stands
+// for. A `{...}` needs an unquoted top-level comma or a valid sequence to
+// count, so `{}`, `{a}` and `${HOME}` are left alone.
+fn first_expansion(chars: &[char]) -> Option<(usize, usize, Vec<String>)> {
+    let mask = unquoted_mask(chars);
+    let is = |i: usize, c: char| chars[i] == c && mask[i];
+
+    for open in (0..chars.len()).filter(|&i| is(i, '{')) {
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        for i in open + 1..chars.len() {
+            if is(i, '{') {
+                depth += 1;
+            } else if is(i, '}') {
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+                depth -= 1;
+            } else if is(i, ',') && depth == 0 {
+                commas.push(i);
+            }
+        }
+        let Some(close) = close else {
+            continue;
+        };
+
+        let text = |from: usize, to: usize| chars[from..to].iter().collect::<String>();
+        if !commas.is_empty() {
+            let mut bounds = vec![open];
+            bounds.extend(&commas);
+            bounds.push(close);
+            let alternatives = bounds
+                .windows(2)
+                .map(|pair| text(pair[0] + 1, pair[1]))
+                .collect();
+            return Some((open, close, alternatives));
+        }
+        if mask[open..=close].iter().all(|&m| m) {
+            if let Some(items) = sequence(&text(open + 1, close)) {
+                return Some((open, close, items));
+            }
+        }
+    }
+    None
+}
+
+// Brace-expands one raw word: `file.{rs,bak}` becomes `file.rs file.bak`,
+// `{1..3}` becomes `1 2 3`, and nested braces expand from the outside in.
+// Braces that are quoted or escaped, or that don't form a valid
+// expression, are left as they are.
+fn expand(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let Some((open, close, alternatives)) = first_expansion(&chars) else {
+        return vec![word.to_string()];
+    };
+    let prefix: String = chars[..open].iter().collect();
+    let suffix: String = chars[close + 1..].iter().collect();
+    alternatives
+        .iter()
+        .flat_map(|alternative| expand(&format!("{prefix}{alternative}{suffix}")))
+        .collect()
+}
+
+// Brace-expands every raw word of a command, before any other expansion.
+pub fn expand_all(words: &[String]) -> Vec<String> {
+    words.iter().flat_map(|word| expand(word)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_alternatives() {
+        assert_eq!(expand("file.{rs,bak}"), vec!["file.rs", "file.bak"]);
+        assert_eq!(expand("dir/{src,tests}"), vec!["dir/src", "dir/tests"]);
+        assert_eq!(expand("{a,}b"), vec!["ab", "b"]);
+        assert_eq!(expand("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+    }
+
+    #[test]
+    fn expand_nested_braces() {
+        assert_eq!(expand("{a,b{1,2},c}"), vec!["a", "b1", "b2", "c"]);
+        assert_eq!(expand("x{{a,b},c}"), vec!["xa", "xb", "xc"]);
+    }
+
+    #[test]
+    fn expand_numeric_sequences() {
+        assert_eq!(expand("{1..4}"), vec!["1", "2", "3", "4"]);
+        assert_eq!(expand("{3..1}"), vec!["3", "2", "1"]);
+        assert_eq!(expand("{0..10..5}"), vec!["0", "5", "10"]);
+        assert_eq!(expand("{-1..1}"), vec!["-1", "0", "1"]);
+        assert_eq!(expand("v{1..2}.txt"), vec!["v1.txt", "v2.txt"]);
+    }
+
+    #[test]
+    fn expand_zero_pads_sequences() {
+        assert_eq!(expand("{01..03}"), vec!["01", "02", "03"]);
+        assert_eq!(expand("{8..010..2}"), vec!["008", "010"]);
+        assert_eq!(expand("{1..12}").len(), 12);
+        assert_eq!(expand("{01..12}")[11], "12");
+    }
+
+    #[test]
+    fn expand_character_sequences() {
+        assert_eq!(expand("{a..e..2}"), vec!["a", "c", "e"]);
+        assert_eq!(expand("{c..a}"), vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn expand_leaves_non_expressions_alone() {
+        for word in [
+            "{}",
+            "{a}",
+            "{a..}",
+            "{1..b}",
+            "a{b",
+            "a}b{",
+            "${HOME}",
+            "${X:-{a,b}}",
+        ] {
+            assert_eq!(expand(word), vec![word]);
+        }
+    }
+
+    #[test]
+    fn expand_ignores_quoted_braces() {
+        for word in [r"\{a,b}", "'{a,b}'", "\"{a,b}\"", r"{a\,b}", "\"{1..3}\""] {
+            assert_eq!(expand(word), vec![word]);
+        }
+        for word in ["$(echo {a,b})", "`echo {a,b}`", "\"$(echo \")\" {a,b})\""] {
+            assert_eq!(expand(word), vec![word]);
+        }
+        assert_eq!(expand("{'a,b',c}"), vec!["'a,b'", "c"]);
+        assert_eq!(expand("{a,b}'{c,d}'"), vec!["a'{c,d}'", "b'{c,d}'"]);
+    }
+
+    #[test]
+    fn expand_all_splices_results_into_place() {
+        let words: Vec<String> = ["mkdir", "-p", "d/{x,y}"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(expand_all(&words), vec!["mkdir", "-p", "d/x", "d/y"]);
+    }
+}
@@ -0,0 +1,569 @@
+use crate::arith;
+use crate::exec::Shell;
+use crate::fileops::{copy_file, move_item, remove_item};
+use crate::interrupt;
+use crate::ls::{list_directory, list_directory_entry};
+use crate::parser::parse_flags;
+use crate::vars::is_valid_name;
+use std::borrow::Cow;
+use std::env;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+// What `cd` does in a pipeline stage running on its own thread, where
+// changing the working directory would change it for the whole shell:
+// just checks that `dir` is a directory that could be entered, and
+// returns its full path.
+fn enterable_dir(dir: &Path) -> io::Result<PathBuf> {
+    let path = fs::canonicalize(dir)?;
+    if !path.is_dir() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::access(c_path.as_ptr(), libc::X_OK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(path)
+}
+
+// Shared by the cp and mv handlers: a single source/destination pair
+// behaves as before, but with more than one source the last argument
+// must be an existing directory that every source gets copied/moved into.
+// Returns 1 if any source failed, 0 otherwise.
+fn copy_or_move_many(
+    args: &[&OsStr],
+    label: &str,
+    op: impl Fn(&Path, &Path) -> Result<(), String>,
+    error: &mut dyn Write,
+) -> i32 {
+    if args.len() < 2 {
+        let _ = writeln!(error, "{label}: missing file operand");
+        return 1;
+    }
+
+    if args.len() == 2 {
+        let source = Path::new(args[0]);
+        let destination = Path::new(args[1]);
+        if let Err(e) = op(source, destination) {
+            if interrupt::interrupted() {
+                return 130;
+            }
+            let _ = writeln!(error, "{}: {}: {}", label, source.display(), e);
+            return 1;
+        }
+        return 0;
+    }
+
+    let destination = Path::new(args[args.len() - 1]);
+    if !destination.is_dir() {
+        let _ = writeln!(
+            error,
+            "{}: target '{}' is not a directory",
+            label,
+            destination.display()
+        );
+        return 1;
+    }
+
+    let mut status = 0;
+    for source in &args[..args.len() - 1] {
+        if interrupt::interrupted() {
+            return 130;
+        }
+        let source = Path::new(source);
+        if let Err(e) = op(source, destination) {
+            if interrupt::interrupted() {
+                return 130;
+            }
+            let _ = writeln!(error, "{}: {}: {}", label, source.display(), e);
+            status = 1;
+        }
+    }
+    status
+}
+
+// `shopt [-s|-u] [-p] [-q] [name...]`: with -s or -u, switches the named
+// options on or off; otherwise reports them (all of them if none are
+// named), failing if any named one is off. With -s/-u but no names, lists
+// the options that are on/off. -p prints in a form that can be run again,
+// and -q prints nothing.
+fn shopt(args: &[&str], shell: &mut Shell, output: &mut dyn Write, error: &mut dyn Write) -> i32 {
+    let mut setting = None;
+    let mut reusable = false;
+    let mut quiet = false;
+    let mut names = Vec::new();
+    for arg in parse_flags(args) {
+        match arg.as_str() {
+            "-s" => setting = Some(true),
+            "-u" => setting = Some(false),
+            "-p" => reusable = true,
+            "-q" => quiet = true,
+            flag if flag.starts_with('-') => {
+                let _ = writeln!(error, "shopt: {flag}: invalid option");
+                return 2;
+            }
+            _ => names.push(arg),
+        }
+    }
+
+    let options = &mut shell.glob_options;
+    for name in &names {
+        if options.flag_mut(name).is_none() {
+            let _ = writeln!(error, "shopt: {name}: invalid shell option name");
+            return 1;
+        }
+    }
+    if let (Some(on), false) = (setting, names.is_empty()) {
+        for name in &names {
+            *options.flag_mut(name).unwrap() = on;
+        }
+        return 0;
+    }
+
+    let shown: Vec<(&str, bool)> = options
+        .flags()
+        .into_iter()
+        .filter(|(name, on)| {
+            if names.is_empty() {
+                setting.is_none_or(|wanted| wanted == *on)
+            } else {
+                names.iter().any(|n| n == name)
+            }
+        })
+        .collect();
+    if !quiet {
+        for (name, on) in &shown {
+            let _ = if reusable {
+                writeln!(output, "shopt {} {name}", if *on { "-s" } else { "-u" })
+            } else {
+                writeln!(output, "{name:<15}\t{}", if *on { "on" } else { "off" })
+            };
+        }
+    }
+    i32::from(!names.is_empty() && shown.iter().any(|(_, on)| !on))
+}
+
+// `set [-o|+o] [name...]`: `-o name` switches an option on and `+o name`
+// switches it off. Either one without a name lists every option, `+o` in a
+// form that can be run again.
+fn set(args: &[&str], shell: &mut Shell, output: &mut dyn Write, error: &mut dyn Write) -> i32 {
+    let options = &mut shell.glob_options;
+    let mut i = 0;
+    while i < args.len() {
+        let on = match args[i] {
+            "-o" => true,
+            "+o" => false,
+            arg => {
+                let _ = writeln!(error, "set: {arg}: invalid option");
+                return 2;
+            }
+        };
+        match args.get(i + 1) {
+            Some(&name) if !name.starts_with(['-', '+']) => {
+                let Some(flag) = options.flag_mut(name) else {
+                    let _ = writeln!(error, "set: {name}: invalid option name");
+                    return 1;
+                };
+                *flag = on;
+                i += 2;
+            }
+            _ => {
+                for (name, value) in options.flags() {
+                    let _ = if on {
+                        writeln!(output, "{name:<15}\t{}", if value { "on" } else { "off" })
+                    } else {
+                        writeln!(output, "set {} {name}", if value { "-o" } else { "+o" })
+                    };
+                }
+                i += 1;
+            }
+        }
+    }
+    0
+}
+
+// `history [n]`: lists the last n lines entered (all of them by default),
+// numbered. `history -c` clears the list and `history -d n` deletes entry
+// n; neither touches the lines already saved to $HISTFILE.
+fn history(args: &[&str], shell: &mut Shell, output: &mut dyn Write, error: &mut dyn Write) -> i32 {
+    match args {
+        ["-c"] => {
+            shell.history.clear();
+            0
+        }
+        ["-d"] => {
+            let _ = writeln!(error, "history: -d: option requires an argument");
+            2
+        }
+        ["-d", position] => {
+            let result = match position.parse::<usize>() {
+                Ok(n) => shell.history.delete(n),
+                Err(_) => Err(format!("{position}: history position out of range")),
+            };
+            match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    let _ = writeln!(error, "history: {e}");
+                    1
+                }
+            }
+        }
+        [flag, ..] if flag.starts_with('-') && *flag != "-" => {
+            let _ = writeln!(error, "history: {flag}: invalid option");
+            2
+        }
+        [_, _, ..] => {
+            let _ = writeln!(error, "history: too many arguments");
+            1
+        }
+        _ => {
+            let entries = shell.history.entries();
+            let count = match args.first().map(|n| n.parse::<usize>()) {
+                None => entries.len(),
+                Some(Ok(n)) => n.min(entries.len()),
+                Some(Err(_)) => {
+                    let _ = writeln!(error, "history: {}: numeric argument required", args[0]);
+                    return 1;
+                }
+            };
+            let first = entries.len() - count;
+            for (i, entry) in entries.iter().enumerate().skip(first) {
+                let _ = writeln!(output, "{:>5}  {entry}", i + 1);
+            }
+            0
+        }
+    }
+}
+
+// The arguments of `ls` or `mkdir` that aren't options, as paths.
+fn operands<'a>(args: &[&'a OsStr]) -> Vec<&'a Path> {
+    args.iter()
+        .filter(|arg| !arg.as_bytes().starts_with(b"-"))
+        .map(|&arg| Path::new(arg))
+        .collect()
+}
+
+// The commands `execute_command` implements itself; anything else is run
+// as an external program.
+pub const BUILTINS: &[&str] = &[
+    "cd", "exit", "echo", "pwd", "cat", "ls", "rm", "cp", "mv", "mkdir", "export", "readonly",
+    "unset", "shopt", "set", "let", "history",
+];
+
+pub fn is_builtin(command: &str) -> bool {
+    BUILTINS.contains(&command)
+}
+
+// Runs a single built-in command, reading piped-in or redirected input, if
+// there is any, from `input`, writing its normal output to `output` and its
+// error messages to `error` (each of which the caller may have pointed at a
+// file, a pipe or the terminal). Returns the command's exit status: 0 on
+// success, 1 on failure, 2 for misuse (as bash's built-ins do).
+// The arguments come as `OsStr`s, since a glob can match a filename that
+// isn't UTF-8. The built-ins that take filenames use them as they are, so
+// every file can be named; the rest see them as text, with any invalid
+// bytes replaced.
+pub fn execute_command(
+    command: &str,
+    args: &[&OsStr],
+    input: Option<&mut dyn Read>,
+    output: &mut dyn Write,
+    error: &mut dyn Write,
+    shell: &mut Shell,
+) -> i32 {
+    let lossy: Vec<Cow<str>> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+    let text: Vec<&str> = lossy.iter().map(AsRef::as_ref).collect();
+    match command {
+        "cd" => {
+            let new_dir: OsString = if text.first() == Some(&"-") {
+                let Some(prev) = shell.vars.get("OLDPWD") else {
+                    let _ = writeln!(error, "cd: OLDPWD not set");
+                    return 1;
+                };
+                prev.into()
+            } else if let Some(dir) = args.first() {
+                dir.to_os_string()
+            } else {
+                let Some(home) = shell.vars.get("HOME") else {
+                    let _ = writeln!(error, "cd: HOME not set");
+                    return 1;
+                };
+                home.into()
+            };
+
+            let current = env::current_dir().ok();
+            let moved = if shell.threaded {
+                enterable_dir(Path::new(&new_dir)).map(Some)
+            } else {
+                env::set_current_dir(Path::new(&new_dir)).map(|()| env::current_dir().ok())
+            };
+            let now = match moved {
+                Ok(now) => now,
+                Err(e) => {
+                    let _ = writeln!(error, "cd: {}: {e}", new_dir.to_string_lossy());
+                    return 1;
+                }
+            };
+            // Like bash, keep $OLDPWD and $PWD in step (unless they've been
+            // made readonly).
+            if let Some(current) = current {
+                let _ = shell.vars.set("OLDPWD", &current.display().to_string());
+            }
+            if let Some(now) = now {
+                let _ = shell.vars.set("PWD", &now.display().to_string());
+            }
+            if text.first() == Some(&"-") {
+                let _ = writeln!(output, "{}", new_dir.to_string_lossy());
+            }
+            0
+        }
+        "exit" => {
+            let status = match text.first() {
+                None => shell.last_status,
+                Some(arg) => match arg.parse::<i64>() {
+                    // Statuses wrap into 0..=255, as they do for a real process.
+                    Ok(n) => (n & 0xff) as i32,
+                    Err(_) => {
+                        let _ = writeln!(error, "exit: {arg}: numeric argument required");
+                        2
+                    }
+                },
+            };
+            shell.exit_status = Some(status);
+            status
+        }
+        "echo" => {
+            let mut line = args.join(OsStr::new(" ")).into_vec();
+            line.push(b'\n');
+            i32::from(output.write_all(&line).is_err())
+        }
+        "pwd" => match env::current_dir() {
+            Ok(dir) => i32::from(writeln!(output, "{}", dir.display()).is_err()),
+            Err(e) => {
+                let _ = writeln!(error, "pwd: {e}");
+                1
+            }
+        },
+        "cat" => {
+            if args.is_empty() {
+                return match input {
+                    Some(input) => i32::from(interrupt::copy(input, output).is_err()),
+                    None => {
+                        let _ = writeln!(error, "cat: No file specified");
+                        1
+                    }
+                };
+            }
+
+            let mut status = 0;
+            for filename in args {
+                let copied =
+                    File::open(filename).and_then(|mut file| interrupt::copy(&mut file, output));
+                match copied {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => return 130,
+                    // The reader went away (e.g. `cat big | head`): stop
+                    // quietly, as a real `cat` killed by SIGPIPE would.
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return 1,
+                    Err(e) => {
+                        let _ = writeln!(error, "cat: {}: {e}", Path::new(filename).display());
+                        status = 1;
+                    }
+                }
+            }
+            status
+        }
+        "ls" => {
+            let parsed_args = parse_flags(&text);
+            let long_format = parsed_args.contains(&"-l".to_string());
+            let all = parsed_args.contains(&"-a".to_string());
+            let classify = parsed_args.contains(&"-F".to_string());
+            let recursive = parsed_args.contains(&"-R".to_string());
+            let paths = operands(args);
+
+            // Like GNU ls, 2 means a path couldn't be accessed at all.
+            let mut status = 0;
+            if paths.is_empty() {
+                if !list_directory(
+                    Path::new("."),
+                    long_format,
+                    all,
+                    classify,
+                    recursive,
+                    output,
+                    error,
+                ) {
+                    status = 2;
+                }
+            } else {
+                let show_headers = paths.len() > 1;
+                for (i, path) in paths.iter().enumerate() {
+                    let p = path.display();
+                    match fs::metadata(path) {
+                        Ok(metadata) if metadata.is_dir() => {
+                            if show_headers {
+                                if i > 0 {
+                                    let _ = writeln!(output);
+                                }
+                                let _ = writeln!(output, "{p}:");
+                            }
+                            if !list_directory(
+                                path,
+                                long_format,
+                                all,
+                                classify,
+                                recursive,
+                                output,
+                                error,
+                            ) {
+                                status = 2;
+                            }
+                        }
+                        Ok(metadata) => {
+                            let _ = writeln!(
+                                output,
+                                "{}",
+                                list_directory_entry(path, &metadata, classify, long_format)
+                            );
+                        }
+                        Err(e) => {
+                            let _ = writeln!(error, "ls: cannot access '{p}': {e}");
+                            status = 2;
+                        }
+                    }
+                }
+            }
+            status
+        }
+        "rm" => {
+            let mut recursive = false;
+            let mut files = Vec::new();
+
+            for &arg in args {
+                if arg == "-r" {
+                    recursive = true;
+                } else {
+                    files.push(arg);
+                }
+            }
+
+            if files.is_empty() {
+                let _ = writeln!(error, "rm: missing operand");
+                return 1;
+            }
+
+            let mut status = 0;
+            for file in files {
+                let path = Path::new(file);
+                if let Err(e) = remove_item(path, recursive) {
+                    let _ = writeln!(error, "rm: {}: {e}", path.display());
+                    status = 1;
+                }
+            }
+            status
+        }
+        "cp" => copy_or_move_many(args, "cp", copy_file, error),
+        "mv" => copy_or_move_many(args, "mv", move_item, error),
+        "mkdir" => {
+            let parsed_args = parse_flags(&text);
+            let make_parents = parsed_args.contains(&"-p".to_string());
+            let dirs = operands(args);
+
+            if dirs.is_empty() {
+                let _ = writeln!(error, "mkdir: missing operand");
+                return 1;
+            }
+
+            let mut status = 0;
+            for path in dirs {
+                let result = if make_parents {
+                    fs::create_dir_all(path)
+                } else {
+                    fs::create_dir(path)
+                };
+                if let Err(e) = result {
+                    let _ = writeln!(error, "mkdir: {}: {e}", path.display());
+                    status = 1;
+                }
+            }
+            status
+        }
+        "export" | "readonly" => {
+            let names: Vec<&str> = text.iter().copied().filter(|&arg| arg != "-p").collect();
+            if names.is_empty() {
+                for line in shell.vars.listing(command, command == "export") {
+                    if writeln!(output, "{line}").is_err() {
+                        return 1;
+                    }
+                }
+                return 0;
+            }
+
+            let mut status = 0;
+            for arg in names {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg, None),
+                };
+                if !is_valid_name(name) {
+                    let _ = writeln!(error, "{command}: `{arg}': not a valid identifier");
+                    status = 1;
+                    continue;
+                }
+                if let Some(value) = value {
+                    if let Err(e) = shell.vars.set(name, value) {
+                        let _ = writeln!(error, "{command}: {e}");
+                        status = 1;
+                        continue;
+                    }
+                }
+                if command == "export" {
+                    shell.vars.export(name);
+                } else {
+                    shell.vars.set_readonly(name);
+                }
+            }
+            status
+        }
+        "unset" => {
+            let mut status = 0;
+            for &name in text.iter().filter(|&&arg| arg != "-v") {
+                if !is_valid_name(name) {
+                    let _ = writeln!(error, "unset: `{name}': not a valid identifier");
+                    status = 1;
+                } else if let Err(e) = shell.vars.unset(name) {
+                    let _ = writeln!(error, "unset: {e}");
+                    status = 1;
+                }
+            }
+            status
+        }
+        "shopt" => shopt(&text, shell, output, error),
+        "set" => set(&text, shell, output, error),
+        "history" => history(&text, shell, output, error),
+        "let" => {
+            // Succeeds if the last expression is non-zero, so `((n > 0))`
+            // works as a condition.
+            if text.is_empty() {
+                let _ = writeln!(error, "let: expression expected");
+                return 1;
+            }
+            let mut last = 0;
+            for expr in &text {
+                match arith::evaluate(expr, &mut shell.vars) {
+                    Ok(value) => last = value,
+                    Err(e) => {
+                        let _ = writeln!(error, "let: {e}");
+                        return 1;
+                    }
+                }
+            }
+            i32::from(last == 0)
+        }
+        _ => unreachable!("{command} is not a built-in"),
+    }
+}
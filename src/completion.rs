@@ -0,0 +1,476 @@
+use crate::builtins::BUILTINS;
+use crate::exec::Shell;
+use crate::external::is_executable;
+use crate::glob::wildcard_matches;
+use crate::users::{get_home_dir_by_name, get_user_names};
+use crate::vars::split_assignment;
+use std::collections::BTreeSet;
+use std::fs;
+use std::mem;
+
+// The options of the built-ins that take any, offered when a word starting
+// with `-` is completed.
+const FLAGS: &[(&str, &[&str])] = &[
+    ("ls", &["-F", "-R", "-a", "-l"]),
+    ("mkdir", &["-p"]),
+    ("rm", &["-r"]),
+];
+
+// One possible completion of the word before the cursor.
+#[derive(Debug, PartialEq)]
+pub struct Candidate {
+    // The text that replaces the word, quoted the same way the word was.
+    pub replacement: String,
+    // How the candidate is shown when they're listed.
+    pub display: String,
+    // What follows the replacement when it's the only candidate: a space
+    // (after any closing quote), or nothing after a directory's `/`.
+    pub suffix: String,
+}
+
+// The candidates for completing the word that starts at character `start`
+// of the text before the cursor.
+#[derive(Debug, Default)]
+pub struct Completions {
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+// The word the cursor is at the end of, and where it sits in its command.
+#[derive(Debug, Default, PartialEq)]
+struct Context {
+    // The character index the word starts at, its text as typed, and its
+    // text with quotes and backslashes taken out.
+    start: usize,
+    raw: String,
+    value: String,
+    // The quote the word leaves open, if any.
+    quote: Option<char>,
+    // The command's name, if the word comes after it; None if the word is
+    // the command name itself.
+    command: Option<String>,
+    // Set when the word follows `<` or `>`.
+    redirect: bool,
+}
+
+// Works out the word being completed from `text`, the line up to the
+// cursor, following the same quoting rules as `tokenize`.
+fn context(text: &str) -> Context {
+    let chars: Vec<char> = text.chars().collect();
+    let mut words: Vec<String> = Vec::new();
+    let mut start = None;
+    let mut value = String::new();
+    let mut quote = None;
+    let mut redirect = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some('\'') => {
+                if c == '\'' {
+                    quote = None;
+                } else {
+                    value.push(c);
+                }
+            }
+            Some(_) => match c {
+                '"' => quote = None,
+                '\\' if chars
+                    .get(i + 1)
+                    .is_some_and(|next| "$`\"\\".contains(*next)) =>
+                {
+                    value.push(chars[i + 1]);
+                    i += 1;
+                }
+                _ => value.push(c),
+            },
+            None if c.is_whitespace() || ";|&()<>".contains(c) => {
+                if start.take().is_some() {
+                    let word = mem::take(&mut value);
+                    if !mem::take(&mut redirect) {
+                        words.push(word);
+                    }
+                }
+                if c == '<' || c == '>' {
+                    redirect = true;
+                } else if !c.is_whitespace() {
+                    words.clear();
+                    redirect = false;
+                }
+            }
+            None => {
+                start.get_or_insert(i);
+                match c {
+                    '\\' if i + 1 < chars.len() => {
+                        value.push(chars[i + 1]);
+                        i += 1;
+                    }
+                    '\'' | '"' => quote = Some(c),
+                    _ => value.push(c),
+                }
+            }
+        }
+        i += 1;
+    }
+
+    let start = start.unwrap_or(chars.len());
+    Context {
+        start,
+        raw: chars[start..].iter().collect(),
+        value,
+        quote,
+        command: words
+            .into_iter()
+            .find(|word| split_assignment(word).is_none()),
+        redirect,
+    }
+}
+
+// Quotes `text` so it reads back as itself: after the opening quote if
+// the word being completed has one, and otherwise with a backslash before
+// each character the shell would treat specially.
+fn quote(text: &str, quote: Option<char>) -> String {
+    match quote {
+        Some('\'') => format!("'{}", text.replace('\'', "'\\''")),
+        Some(q) => {
+            let mut quoted = q.to_string();
+            for c in text.chars() {
+                if "\"$`\\".contains(c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted
+        }
+        None => {
+            let mut quoted = String::new();
+            for (i, c) in text.chars().enumerate() {
+                if c.is_whitespace() || "'\"\\$`;&|<>()*?[]{}#!".contains(c) || (i == 0 && c == '~')
+                {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted
+        }
+    }
+}
+
+// A glob pattern matching names that start with `prefix`.
+fn prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::new();
+    for c in prefix.chars() {
+        if "*?[]\\".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('*');
+    pattern
+}
+
+// Built-ins and the programs in `$PATH` whose names start with `prefix`.
+fn commands(prefix: &str, shell: &Shell) -> BTreeSet<String> {
+    let mut names: BTreeSet<String> = BUILTINS
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    for dir in shell.vars.get("PATH").unwrap_or("").split(':') {
+        let dir = if dir.is_empty() { "." } else { dir };
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+// Files in the directory `value` names (up to its last `/`) that start
+// with the rest of it. Names starting with `.` are only offered when the
+// rest does too. With `executables_only`, for the command name, only
+// directories and programs are offered.
+fn paths(context: &Context, shell: &Shell, executables_only: bool) -> Vec<Candidate> {
+    let value = &context.value;
+    let (dir_part, prefix) = match value.rfind('/') {
+        Some(i) => value.split_at(i + 1),
+        None => ("", value.as_str()),
+    };
+    // A leading `~` or `~user` is kept as typed but expanded for reading
+    // the directory.
+    let mut dir = dir_part.to_string();
+    let tilde = dir_part
+        .strip_prefix('~')
+        .filter(|_| context.raw.starts_with('~'));
+    if let (Some(tilde), None) = (tilde, context.quote) {
+        if let Some((user, rest)) = tilde.split_once('/') {
+            let home = match user {
+                "" => shell.vars.get("HOME").map(str::to_string),
+                user => get_home_dir_by_name(user),
+            };
+            if let Some(home) = home {
+                dir = format!("{home}/{rest}");
+            }
+        }
+    }
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { &dir }) else {
+        return Vec::new();
+    };
+
+    let pattern = prefix_pattern(prefix);
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if (name.starts_with('.') && !prefix.starts_with('.')) || !wildcard_matches(&pattern, &name)
+        {
+            continue;
+        }
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        if executables_only && !is_dir && !is_executable(&path) {
+            continue;
+        }
+        let slash = if is_dir { "/" } else { "" };
+        let full = format!("{dir_part}{name}{slash}");
+        let replacement = match full.strip_prefix('~') {
+            Some(rest) if context.quote.is_none() && context.raw.starts_with('~') => {
+                format!("~{}", quote(rest, None))
+            }
+            _ => quote(&full, context.quote),
+        };
+        let suffix = if is_dir {
+            String::new()
+        } else {
+            format!("{} ", context.quote.map(String::from).unwrap_or_default())
+        };
+        candidates.push(Candidate {
+            replacement,
+            display: format!("{name}{slash}"),
+            suffix,
+        });
+    }
+    candidates
+}
+
+// Candidates that all end with `suffix`, made from plain names.
+fn named(
+    names: impl IntoIterator<Item = String>,
+    context: &Context,
+    suffix: &str,
+) -> Vec<Candidate> {
+    names
+        .into_iter()
+        .map(|name| Candidate {
+            replacement: quote(&name, context.quote),
+            display: name,
+            suffix: format!(
+                "{}{suffix}",
+                context.quote.map(String::from).unwrap_or_default()
+            ),
+        })
+        .collect()
+}
+
+// The completions for the word ending at the cursor, given `text`, the
+// line up to the cursor:
+//
+// - `$NAME` or `${NAME`: the names of set variables
+// - `~user` (before any `/`): user names
+// - the command name: built-ins and `$PATH` programs, or paths if it
+//   has a `/`
+// - `-flag` after `ls`, `mkdir` or `rm`: that command's options
+// - anything else: file and directory paths
+pub fn complete(text: &str, shell: &Shell) -> Completions {
+    let context = context(text);
+    let mut completions = Completions {
+        start: context.start,
+        candidates: Vec::new(),
+    };
+
+    let dollar = context
+        .raw
+        .rfind('$')
+        .filter(|&i| !context.raw[..i].ends_with('\\'));
+    if let (Some(dollar), false) = (dollar, context.quote == Some('\'')) {
+        let after = &context.raw[dollar + 1..];
+        let (braced, prefix) = match after.strip_prefix('{') {
+            Some(prefix) => (true, prefix),
+            None => (false, after),
+        };
+        if prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            completions.start = context.start + context.raw[..dollar].chars().count();
+            let (open, close) = if braced { ("${", "}") } else { ("$", "") };
+            completions.candidates = shell
+                .vars
+                .names()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| Candidate {
+                    replacement: format!("{open}{name}"),
+                    display: name.to_string(),
+                    suffix: close.to_string(),
+                })
+                .collect();
+            return completions;
+        }
+    }
+
+    if context.quote.is_none() && context.raw.starts_with('~') && !context.raw.contains('/') {
+        let prefix = &context.raw[1..];
+        let users: BTreeSet<String> = get_user_names()
+            .into_iter()
+            .filter(|user| user.starts_with(prefix))
+            .collect();
+        completions.candidates = users
+            .into_iter()
+            .map(|user| Candidate {
+                replacement: format!("~{user}"),
+                display: format!("~{user}"),
+                suffix: "/".to_string(),
+            })
+            .collect();
+        return completions;
+    }
+
+    completions.candidates = match &context.command {
+        None if context.redirect => paths(&context, shell, false),
+        None if context.value.contains('/') => paths(&context, shell, true),
+        None => named(commands(&context.value, shell), &context, " "),
+        Some(command) if context.value.starts_with('-') && !context.redirect => {
+            let flags = FLAGS
+                .iter()
+                .find(|(name, _)| name == command)
+                .map_or(&[][..], |(_, flags)| flags);
+            let matching = flags
+                .iter()
+                .filter(|flag| flag.starts_with(&context.value))
+                .map(|flag| flag.to_string());
+            named(matching, &context, " ")
+        }
+        Some(_) => paths(&context, shell, false),
+    };
+    completions
+        .candidates
+        .sort_by(|a, b| a.display.cmp(&b.display));
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "zero_shell_completion_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn replacements(text: &str, shell: &Shell) -> Vec<String> {
+        complete(text, shell)
+            .candidates
+            .into_iter()
+            .map(|c| format!("{}{}", c.replacement, c.suffix))
+            .collect()
+    }
+
+    #[test]
+    fn context_finds_the_word_and_its_command() {
+        let ctx = context("ls -l 'my fi");
+        assert_eq!(ctx.start, 6);
+        assert_eq!(ctx.raw, "'my fi");
+        assert_eq!(ctx.value, "my fi");
+        assert_eq!(ctx.quote, Some('\''));
+        assert_eq!(ctx.command.as_deref(), Some("ls"));
+
+        let ctx = context("cat a | X=1 gr");
+        assert_eq!((ctx.value.as_str(), ctx.command), ("gr", None));
+
+        let ctx = context("echo a\\ b > ou");
+        assert_eq!(ctx.command.as_deref(), Some("echo"));
+        assert!(ctx.redirect);
+
+        let ctx = context("echo ");
+        assert_eq!((ctx.start, ctx.raw.as_str()), (5, ""));
+    }
+
+    #[test]
+    fn quote_matches_the_word_being_completed() {
+        assert_eq!(quote("my file (1)", None), "my\\ file\\ \\(1\\)");
+        assert_eq!(quote("~x~", None), "\\~x~");
+        assert_eq!(quote("it's", Some('\'')), "'it'\\''s");
+        assert_eq!(quote("a \"$b\"", Some('"')), "\"a \\\"\\$b\\\"");
+    }
+
+    #[test]
+    fn complete_offers_paths_quoted_as_typed() {
+        let dir = temp_dir("paths");
+        fs::create_dir(dir.join("sub dir")).unwrap();
+        fs::write(dir.join("sub dir").join("notes.txt"), "").unwrap();
+        fs::write(dir.join("summary"), "").unwrap();
+        fs::write(dir.join(".secret"), "").unwrap();
+        let shell = Shell::default();
+        let base = dir.to_str().unwrap();
+
+        assert_eq!(
+            replacements(&format!("cat {base}/su"), &shell),
+            [format!("{base}/sub\\ dir/"), format!("{base}/summary ")]
+        );
+        assert_eq!(
+            replacements(&format!("cat \"{base}/sub dir/n"), &shell),
+            [format!("\"{base}/sub dir/notes.txt\" ")]
+        );
+        assert_eq!(
+            replacements(&format!("cat {base}/."), &shell),
+            [format!("{base}/.secret ")]
+        );
+        assert!(replacements(&format!("cat {base}/x"), &shell).is_empty());
+    }
+
+    #[test]
+    fn complete_offers_commands_in_command_position() {
+        let dir = temp_dir("commands");
+        fs::write(dir.join("zs_tool"), "").unwrap();
+        fs::write(dir.join("zs_data"), "").unwrap();
+        let mut permissions = fs::metadata(dir.join("zs_tool")).unwrap().permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        fs::set_permissions(dir.join("zs_tool"), permissions).unwrap();
+        let mut shell = Shell::default();
+        shell.vars.set("PATH", dir.to_str().unwrap()).unwrap();
+
+        assert_eq!(replacements("zs_", &shell), ["zs_tool "]);
+        assert_eq!(replacements("echo hi; mkd", &shell), ["mkdir "]);
+        assert_eq!(replacements("ls -", &shell), ["-F ", "-R ", "-a ", "-l "]);
+        assert_eq!(replacements("rm -", &shell), ["-r "]);
+    }
+
+    #[test]
+    fn complete_offers_variable_names() {
+        let mut shell = Shell::default();
+        shell.vars.set("HOME", "/h").unwrap();
+        shell.vars.set("HOSTNAME", "box").unwrap();
+        let completions = complete("echo \"$HO", &shell);
+        assert_eq!(completions.start, 6);
+        assert_eq!(replacements("echo \"$HO", &shell), ["$HOME", "$HOSTNAME"]);
+        assert_eq!(replacements("echo a${HOS", &shell), ["${HOSTNAME}"]);
+        assert!(replacements("echo '$HO", &shell).is_empty());
+    }
+
+    #[test]
+    fn complete_offers_user_names_after_a_tilde() {
+        let shell = Shell::default();
+        assert!(replacements("cd ~roo", &shell).contains(&"~root/".to_string()));
+    }
+}
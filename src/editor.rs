@@ -0,0 +1,1138 @@
+use crate::completion::Completions;
+use crate::prompt::{IGNORE_END, IGNORE_START};
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::ops::Range;
+
+// A key press, decoded from the bytes the terminal sends for it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Char(char),
+    // Ctrl plus a letter, given as the lower-case letter.
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    // Alt-Backspace and Alt-D.
+    KillWordLeft,
+    KillWordRight,
+    // A sequence we don't handle, which is ignored.
+    Unknown,
+}
+
+// Reads one key from `next_byte`, which yields the terminal's input a byte
+// at a time (None at end of input). Understands UTF-8, the `ESC [` and
+// `ESC O` sequences that arrow, Home, End and Delete keys send (including
+// the Ctrl-arrow forms), and Alt-letter as `ESC` followed by the letter.
+fn read_key(next_byte: &mut dyn FnMut() -> io::Result<Option<u8>>) -> io::Result<Option<Key>> {
+    let Some(byte) = next_byte()? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => match next_byte()? {
+            Some(b'[') => {
+                let mut params = String::new();
+                let last = loop {
+                    match next_byte()? {
+                        Some(b @ 0x40..=0x7e) => break b,
+                        Some(b) => params.push(char::from(b)),
+                        None => return Ok(Some(Key::Unknown)),
+                    }
+                };
+                match (params.as_str(), last) {
+                    ("", b'A') => Key::Up,
+                    ("", b'B') => Key::Down,
+                    ("", b'C') => Key::Right,
+                    ("", b'D') => Key::Left,
+                    ("", b'H') | ("1" | "7", b'~') => Key::Home,
+                    ("", b'F') | ("4" | "8", b'~') => Key::End,
+                    ("3", b'~') => Key::Delete,
+                    ("1;5" | "1;3", b'C') => Key::WordRight,
+                    ("1;5" | "1;3", b'D') => Key::WordLeft,
+                    _ => Key::Unknown,
+                }
+            }
+            Some(b'O') => match next_byte()? {
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                _ => Key::Unknown,
+            },
+            Some(b'b') => Key::WordLeft,
+            Some(b'f') => Key::WordRight,
+            Some(b'd') => Key::KillWordRight,
+            Some(0x7f | 0x08) => Key::KillWordLeft,
+            _ => Key::Unknown,
+        },
+        0x01..=0x1a => Key::Ctrl(char::from(b'a' + byte - 1)),
+        0x00..=0x1f => Key::Unknown,
+        _ => {
+            // The first byte of a UTF-8 sequence says how many follow.
+            let len = match byte {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match next_byte()? {
+                    Some(b) => bytes.push(b),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+// How many terminal columns `c` takes up: 2 for wide East Asian characters
+// and emoji, 0 for combining marks and zero-width joiners/selectors, and 1
+// for everything else.
+fn char_width(c: char) -> usize {
+    match u32::from(c) {
+        0x0300..=0x036f
+        | 0x1ab0..=0x1aff
+        | 0x1dc0..=0x1dff
+        | 0x200b..=0x200f
+        | 0x20d0..=0x20ff
+        | 0xfe00..=0xfe0f
+        | 0xfe20..=0xfe2f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x2fffd
+        | 0x30000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+// Where the cursor ends up, as (row, column) from the start of the prompt,
+// after printing `text` on a terminal `cols` wide. A wide character that
+// doesn't fit at the end of a row moves to the next one, as terminals do.
+fn position(text: impl IntoIterator<Item = char>, cols: usize) -> (usize, usize) {
+    let (mut row, mut col) = (0, 0);
+    for c in text {
+        if c == '\n' {
+            row += 1;
+            col = 0;
+            continue;
+        }
+        let width = char_width(c);
+        if col + width > cols {
+            row += 1;
+            col = 0;
+        }
+        col += width;
+    }
+    if col >= cols {
+        (row + 1, 0)
+    } else {
+        (row, col)
+    }
+}
+
+// The characters of `prompt` that show on screen: all but the runs that
+// `\[` and `\]` marked as non-printing.
+fn visible(prompt: &str) -> impl Iterator<Item = char> + '_ {
+    let mut hidden = false;
+    prompt.chars().filter(move |&c| match c {
+        IGNORE_START => {
+            hidden = true;
+            false
+        }
+        IGNORE_END => {
+            hidden = false;
+            false
+        }
+        _ => !hidden,
+    })
+}
+
+// The line being edited, with the cursor as an index into its characters.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    // Replaces the characters from `start` up to the cursor with `text`,
+    // leaving the cursor after it.
+    fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        self.chars.splice(start..self.cursor, chars.iter().copied());
+        self.cursor = start + chars.len();
+    }
+
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.chars.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    // Removes the characters from `start` to `end`, returning them.
+    fn cut(&mut self, start: usize, end: usize) -> String {
+        self.cursor = start;
+        self.chars.drain(start..end).collect()
+    }
+
+    // The start of the word before the cursor, where words are runs of
+    // letters and digits.
+    fn word_left(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    // The end of the word after the cursor.
+    fn word_right(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.chars.len() && self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    // The start of the whitespace-separated word before the cursor, for
+    // Ctrl-W.
+    fn big_word_left(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+}
+
+// The display width of `text` in terminal columns.
+fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+// Lays `items` out in as many columns as fit in `width`, reading down
+// each column in turn as `ls` does, and returns the rows.
+fn columns(items: &[String], width: usize) -> Vec<String> {
+    let column_width = items.iter().map(|item| text_width(item)).max().unwrap_or(0) + 2;
+    let count = (width / column_width).max(1);
+    let rows = items.len().div_ceil(count);
+    (0..rows)
+        .map(|row| {
+            let mut text = String::new();
+            for item in items.iter().skip(row).step_by(rows) {
+                text.push_str(item);
+                text.push_str(&" ".repeat(column_width - text_width(item)));
+            }
+            text.trim_end().to_string()
+        })
+        .collect()
+}
+
+// What pressing Tab did.
+#[derive(Debug, PartialEq)]
+enum TabOutcome {
+    // The word was completed, fully or as far as the candidates agree.
+    Completed,
+    // There was nothing to complete, or nothing more on a first Tab.
+    Bell,
+    // A second Tab with several candidates: list them.
+    List(Vec<String>),
+}
+
+// What a key press does to the line being read.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Continue,
+    // Enter: the line is done.
+    Accept,
+    // Ctrl-C: abandon the line.
+    Cancel,
+    // Ctrl-D on an empty line.
+    EndOfInput,
+    ClearScreen,
+}
+
+// What `Editor::read_line` read.
+#[derive(Debug, PartialEq)]
+pub enum Entered {
+    Line(String),
+    // Ctrl-C: the line was abandoned, along with whatever it was part of.
+    Cancelled,
+    // Ctrl-D on an empty line, or stdin closed.
+    EndOfInput,
+}
+
+// An incremental history search in progress, started by Ctrl-R (going
+// back) or Ctrl-S (going forward).
+struct Search {
+    query: String,
+    forward: bool,
+    // The history entry last matched, and the character offset of the
+    // match in it.
+    found: Option<(usize, usize)>,
+    // Set when no entry matches the query as it now stands.
+    failed: bool,
+    // Where the search started, and the line as it was then, which Ctrl-G
+    // goes back to.
+    start_index: usize,
+    original: Line,
+}
+
+impl Search {
+    // The prompt shown in place of the usual one, as readline does it.
+    fn prompt(&self) -> String {
+        format!(
+            "({}{}i-search)`{}': ",
+            if self.failed { "failed " } else { "" },
+            if self.forward { "" } else { "reverse-" },
+            self.query
+        )
+    }
+}
+
+// Puts the terminal into raw mode for as long as it's alive, restoring the
+// original settings when dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &raw mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        // Keys arrive one at a time, unechoed, with Ctrl-C/Z/S/V and a
+        // carriage return for Enter passed through as ordinary input.
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw const raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(
+                libc::STDIN_FILENO,
+                libc::TCSADRAIN,
+                &raw const self.original,
+            );
+        }
+    }
+}
+
+// Reads a single byte straight from stdin, bypassing any buffering.
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        let n = unsafe { libc::read(libc::STDIN_FILENO, (&raw mut byte).cast(), 1) };
+        match n {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+// The terminal's width in columns, or 80 if it can't be found out.
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &raw mut size) } == 0;
+    if ok && size.ws_col > 0 {
+        usize::from(size.ws_col)
+    } else {
+        80
+    }
+}
+
+// Reads command lines. On a terminal it puts it in raw mode and handles
+// the keys itself, emacs style:
+//
+//   Left/Right, Ctrl-B/F         move by a character
+//   Alt-B/F, Ctrl-Left/Right     move by a word
+//   Home/End, Ctrl-A/E           go to the start/end of the line
+//   Backspace, Delete, Ctrl-D    delete a character
+//   Ctrl-K/U                     cut to the end/start of the line
+//   Ctrl-W                       cut the whitespace-separated word before
+//   Alt-Backspace/Alt-D          cut the word before/after
+//   Ctrl-Y                       paste the last cut text
+//   Up/Down, Ctrl-P/N            step through earlier lines
+//   Ctrl-R/S                     search back/forward through earlier lines
+//   Ctrl-L                       clear the screen
+//   Ctrl-C                       abandon the line
+//
+// Anywhere else (a pipe or a file) it reads plain lines.
+#[derive(Default)]
+pub struct Editor {
+    // The text most recently cut, for Ctrl-Y.
+    cut_buffer: String,
+    // While stepping through history: the entry shown, and the line that
+    // was being typed before stepping away from it.
+    history_index: usize,
+    draft: String,
+    // The row of the rendered line the cursor was left on, so the next
+    // redraw knows how far up the line starts.
+    cursor_row: usize,
+    search: Option<Search>,
+    // The last search's query, which Ctrl-R/S with nothing typed reuses.
+    last_query: String,
+    // Whether the previous key was Tab, so a second one lists candidates.
+    last_tab: bool,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor::default()
+    }
+
+    // Shows `prompt` and reads a line, without its newline, with Up/Down
+    // stepping through `history` and Tab completing the word before the
+    // cursor from what `complete` finds for the text up to it.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        history: &[String],
+        complete: &dyn Fn(&str) -> Completions,
+    ) -> io::Result<Entered> {
+        let is_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        let raw_mode = if is_tty { RawMode::enable().ok() } else { None };
+        if raw_mode.is_none() {
+            print!("{}", prompt.replace([IGNORE_START, IGNORE_END], ""));
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(Entered::EndOfInput);
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            return Ok(Entered::Line(line.to_string()));
+        }
+
+        let mut line = Line::default();
+        self.history_index = history.len();
+        self.cursor_row = 0;
+        self.search = None;
+        self.last_tab = false;
+        self.redraw(prompt, &line)?;
+        loop {
+            let Some(key) = read_key(&mut read_byte)? else {
+                return Ok(Entered::EndOfInput);
+            };
+            if key == Key::Tab && self.search.is_none() {
+                let before: String = line.chars[..line.cursor].iter().collect();
+                match self.complete(&mut line, complete(&before)) {
+                    TabOutcome::Completed => self.redraw(prompt, &line)?,
+                    TabOutcome::Bell => {
+                        print!("\x07");
+                        io::stdout().flush()?;
+                    }
+                    TabOutcome::List(items) => {
+                        // List below the whole line, then start it afresh.
+                        let cursor = mem::replace(&mut line.cursor, line.chars.len());
+                        self.redraw(prompt, &line)?;
+                        line.cursor = cursor;
+                        print!("\r\n");
+                        for row in columns(&items, terminal_width()) {
+                            print!("{row}\r\n");
+                        }
+                        self.cursor_row = 0;
+                        self.redraw(prompt, &line)?;
+                    }
+                }
+                continue;
+            }
+            self.last_tab = false;
+            match self.apply(&mut line, key, history) {
+                Outcome::Continue => self.redraw(prompt, &line)?,
+                Outcome::Accept => {
+                    line.cursor = line.chars.len();
+                    self.redraw(prompt, &line)?;
+                    print!("\r\n");
+                    io::stdout().flush()?;
+                    return Ok(Entered::Line(line.text()));
+                }
+                Outcome::Cancel => {
+                    line.cursor = line.chars.len();
+                    self.search = None;
+                    self.redraw(prompt, &line)?;
+                    print!("^C\r\n");
+                    io::stdout().flush()?;
+                    return Ok(Entered::Cancelled);
+                }
+                Outcome::EndOfInput => return Ok(Entered::EndOfInput),
+                Outcome::ClearScreen => {
+                    print!("\x1b[H\x1b[2J");
+                    self.cursor_row = 0;
+                    self.redraw(prompt, &line)?;
+                }
+            }
+        }
+    }
+
+    // Carries out one key press on `line`.
+    fn apply(&mut self, line: &mut Line, key: Key, history: &[String]) -> Outcome {
+        if self.search.is_some() {
+            return self.apply_search(line, key, history);
+        }
+        let len = line.chars.len();
+        match key {
+            Key::Char(c) => line.insert(&c.to_string()),
+            Key::Enter => return Outcome::Accept,
+            Key::Ctrl('c') => return Outcome::Cancel,
+            Key::Ctrl('d') if len == 0 => return Outcome::EndOfInput,
+            Key::Ctrl('l') => return Outcome::ClearScreen,
+            Key::Left | Key::Ctrl('b') => line.cursor = line.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => line.cursor = (line.cursor + 1).min(len),
+            Key::Home | Key::Ctrl('a') => line.cursor = 0,
+            Key::End | Key::Ctrl('e') => line.cursor = len,
+            Key::WordLeft => line.cursor = line.word_left(),
+            Key::WordRight => line.cursor = line.word_right(),
+            Key::Backspace if line.cursor > 0 => {
+                line.cut(line.cursor - 1, line.cursor);
+            }
+            Key::Delete | Key::Ctrl('d') if line.cursor < len => {
+                line.cut(line.cursor, line.cursor + 1);
+            }
+            Key::Ctrl('k') => self.cut_buffer = line.cut(line.cursor, len),
+            Key::Ctrl('u') => self.cut_buffer = line.cut(0, line.cursor),
+            Key::Ctrl('w') => self.cut_buffer = line.cut(line.big_word_left(), line.cursor),
+            Key::KillWordLeft => self.cut_buffer = line.cut(line.word_left(), line.cursor),
+            Key::KillWordRight => {
+                let end = line.word_right();
+                self.cut_buffer = line.cut(line.cursor, end);
+            }
+            Key::Ctrl('y') => line.insert(&self.cut_buffer.clone()),
+            Key::Up | Key::Ctrl('p') => self.step_history(line, -1, history),
+            Key::Down | Key::Ctrl('n') => self.step_history(line, 1, history),
+            Key::Ctrl('r') | Key::Ctrl('s') => {
+                if self.history_index == history.len() {
+                    self.draft = line.text();
+                }
+                self.search = Some(Search {
+                    query: String::new(),
+                    forward: key == Key::Ctrl('s'),
+                    found: None,
+                    failed: false,
+                    start_index: self.history_index,
+                    original: line.clone(),
+                });
+            }
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    // Completes the word before the cursor from `completions`: replaces it
+    // with the only candidate (and a space, or whatever else it says should
+    // follow), or with as much as all the candidates have in common. When
+    // they have no more than that in common, a second Tab in a row asks
+    // for them to be listed.
+    fn complete(&mut self, line: &mut Line, completions: Completions) -> TabOutcome {
+        let again = mem::replace(&mut self.last_tab, true);
+        let start = completions.start.min(line.cursor);
+        let typed = line.cursor - start;
+        match completions.candidates.as_slice() {
+            [] => TabOutcome::Bell,
+            [only] => {
+                let text = format!("{}{}", only.replacement, only.suffix);
+                line.replace_before_cursor(start, &text);
+                TabOutcome::Completed
+            }
+            candidates => {
+                let mut common: Vec<char> = candidates[0].replacement.chars().collect();
+                for candidate in &candidates[1..] {
+                    let same = common
+                        .iter()
+                        .zip(candidate.replacement.chars())
+                        .take_while(|(a, b)| **a == *b)
+                        .count();
+                    common.truncate(same);
+                }
+                if common.len() > typed {
+                    line.replace_before_cursor(start, &common.iter().collect::<String>());
+                    TabOutcome::Completed
+                } else if again {
+                    TabOutcome::List(candidates.iter().map(|c| c.display.clone()).collect())
+                } else {
+                    TabOutcome::Bell
+                }
+            }
+        }
+    }
+
+    // Carries out a key press during a search. Typing extends the query
+    // and Backspace shortens it, Ctrl-R/S move on to the next match back or
+    // forward, and Ctrl-G gives up and puts the line back as it was. Any
+    // other key ends the search, leaving the match to be edited, and then
+    // has its usual effect (so Enter runs it).
+    fn apply_search(&mut self, line: &mut Line, key: Key, history: &[String]) -> Outcome {
+        let Some(search) = self.search.as_mut() else {
+            return Outcome::Continue;
+        };
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                self.find(line, history, false);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                search.found = None;
+                search.failed = false;
+                if search.query.is_empty() {
+                    self.history_index = search.start_index;
+                    *line = search.original.clone();
+                } else {
+                    self.find(line, history, false);
+                }
+            }
+            Key::Ctrl('r') | Key::Ctrl('s') => {
+                search.forward = key == Key::Ctrl('s');
+                if search.query.is_empty() {
+                    search.query = self.last_query.clone();
+                }
+                if !search.query.is_empty() {
+                    self.find(line, history, true);
+                }
+            }
+            Key::Ctrl('g') => {
+                self.history_index = search.start_index;
+                *line = search.original.clone();
+                self.search = None;
+            }
+            _ => {
+                if !search.query.is_empty() {
+                    self.last_query = search.query.clone();
+                }
+                self.search = None;
+                return self.apply(line, key, history);
+            }
+        }
+        Outcome::Continue
+    }
+
+    // Looks for the nearest history entry containing the search query, in
+    // the search's direction from the last match (or from where the search
+    // started), and shows it with the cursor at the match. `skip_current`
+    // moves past the last match rather than checking it again.
+    fn find(&mut self, line: &mut Line, history: &[String], skip_current: bool) {
+        let Some(search) = self.search.as_mut() else {
+            return;
+        };
+        let current = search.found.map(|(index, _)| index);
+        let candidates: Vec<usize> = if search.forward {
+            let start = match current {
+                Some(index) if skip_current => index + 1,
+                Some(index) => index,
+                None => search.start_index,
+            };
+            (start..history.len()).collect()
+        } else {
+            let end = match current {
+                Some(index) if skip_current => index,
+                Some(index) => index + 1,
+                None => search.start_index.min(history.len()),
+            };
+            (0..end).rev().collect()
+        };
+        for index in candidates {
+            let entry = &history[index];
+            let byte = if search.forward {
+                entry.find(&search.query)
+            } else {
+                entry.rfind(&search.query)
+            };
+            if let Some(byte) = byte {
+                let offset = entry[..byte].chars().count();
+                search.found = Some((index, offset));
+                search.failed = false;
+                self.history_index = index;
+                line.set(entry);
+                line.cursor = offset;
+                return;
+            }
+        }
+        search.failed = true;
+    }
+
+    // Redraws the line after the prompt, or after the search prompt with
+    // the match highlighted while a search is going on.
+    fn redraw(&mut self, prompt: &str, line: &Line) -> io::Result<()> {
+        let Some(search) = &self.search else {
+            return self.refresh(prompt, line, None);
+        };
+        let prompt = search.prompt();
+        let highlight = match search.found {
+            Some((_, offset)) if !search.failed => {
+                Some(offset..offset + search.query.chars().count())
+            }
+            _ => None,
+        };
+        self.refresh(&prompt, line, highlight)
+    }
+
+    // Replaces the line with the history entry `step` places away, keeping
+    // what was being typed so that stepping back down past the newest
+    // entry brings it back.
+    fn step_history(&mut self, line: &mut Line, step: isize, history: &[String]) {
+        let Some(index) = self
+            .history_index
+            .checked_add_signed(step)
+            .filter(|&i| i <= history.len())
+        else {
+            return;
+        };
+        if self.history_index == history.len() {
+            self.draft = line.text();
+        }
+        self.history_index = index;
+        match history.get(index) {
+            Some(entry) => line.set(entry),
+            None => line.set(&self.draft),
+        }
+    }
+
+    // Redraws the prompt and line from scratch and puts the cursor back in
+    // place, showing the characters in `highlight` in reverse video. The
+    // line may wrap over several rows, so this goes back up to the row the
+    // prompt is on, clears everything below, and works out the cursor's row
+    // and column from the characters' display widths.
+    fn refresh(
+        &mut self,
+        prompt: &str,
+        line: &Line,
+        highlight: Option<Range<usize>>,
+    ) -> io::Result<()> {
+        let cols = terminal_width();
+        let mut out = String::new();
+        if self.cursor_row > 0 {
+            out.push_str(&format!("\x1b[{}A", self.cursor_row));
+        }
+        out.push_str("\r\x1b[J");
+        out.push_str(&prompt.replace([IGNORE_START, IGNORE_END], ""));
+        for (i, c) in line.chars.iter().enumerate() {
+            match &highlight {
+                Some(range) if i == range.start => out.push_str("\x1b[7m"),
+                _ => {}
+            }
+            out.push(*c);
+            match &highlight {
+                Some(range) if i + 1 == range.end => out.push_str("\x1b[0m"),
+                _ => {}
+            }
+        }
+
+        let (end_row, end_col) = position(visible(prompt).chain(line.text().chars()), cols);
+        // At the very end of a row the terminal hasn't moved down yet.
+        let ends_with_newline = line.chars.is_empty() && prompt.ends_with('\n');
+        if end_col == 0 && end_row > 0 && !ends_with_newline {
+            out.push_str("\r\n");
+        }
+        let before_cursor = visible(prompt).chain(line.chars[..line.cursor].iter().copied());
+        let (row, col) = position(before_cursor, cols);
+        if end_row > row {
+            out.push_str(&format!("\x1b[{}A", end_row - row));
+        }
+        out.push('\r');
+        if col > 0 {
+            out.push_str(&format!("\x1b[{col}C"));
+        }
+        self.cursor_row = row;
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut bytes = bytes.iter().copied();
+        let mut next = || Ok(bytes.next());
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(&mut next).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    fn type_keys(editor: &mut Editor, line: &mut Line, keys: &[Key]) {
+        for &key in keys {
+            editor.apply(line, key, &[]);
+        }
+    }
+
+    fn typed(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    #[test]
+    fn read_key_decodes_escape_sequences() {
+        assert_eq!(
+            keys(b"\x1b[A\x1b[D\x1b[H\x1b[4~\x1bOF\x1b[3~\x1b[1;5C\x1bb"),
+            vec![
+                Key::Up,
+                Key::Left,
+                Key::Home,
+                Key::End,
+                Key::End,
+                Key::Delete,
+                Key::WordRight,
+                Key::WordLeft,
+            ]
+        );
+        assert_eq!(keys(b"\x1b[99Z"), vec![Key::Unknown]);
+    }
+
+    #[test]
+    fn read_key_decodes_control_keys_and_utf8() {
+        assert_eq!(
+            keys("a\u{1}\r\x7fé漢".as_bytes()),
+            vec![
+                Key::Char('a'),
+                Key::Ctrl('a'),
+                Key::Enter,
+                Key::Backspace,
+                Key::Char('é'),
+                Key::Char('漢'),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_moves_and_edits() {
+        let mut editor = Editor::new();
+        let mut line = Line::default();
+        type_keys(&mut editor, &mut line, &typed("ech"));
+        type_keys(&mut editor, &mut line, &[Key::Home, Key::Delete, Key::End]);
+        type_keys(&mut editor, &mut line, &typed("o"));
+        type_keys(
+            &mut editor,
+            &mut line,
+            &[Key::Left, Key::Left, Key::Backspace],
+        );
+        assert_eq!(line.text(), "ho");
+        assert_eq!(line.cursor, 0);
+        assert_eq!(editor.apply(&mut line, Key::Enter, &[]), Outcome::Accept);
+    }
+
+    #[test]
+    fn apply_cuts_and_pastes() {
+        let mut editor = Editor::new();
+        let mut line = Line::default();
+        type_keys(&mut editor, &mut line, &typed("cp src/a.rs dst"));
+        type_keys(&mut editor, &mut line, &[Key::Ctrl('w')]);
+        assert_eq!(line.text(), "cp src/a.rs ");
+        type_keys(&mut editor, &mut line, &[Key::Home, Key::Ctrl('y')]);
+        assert_eq!(line.text(), "dstcp src/a.rs ");
+        type_keys(&mut editor, &mut line, &[Key::WordRight, Key::Ctrl('k')]);
+        assert_eq!(line.text(), "dstcp");
+        type_keys(
+            &mut editor,
+            &mut line,
+            &[Key::Ctrl('u'), Key::End, Key::Ctrl('y')],
+        );
+        assert_eq!(line.text(), "dstcp");
+        type_keys(&mut editor, &mut line, &typed(" a.b"));
+        type_keys(&mut editor, &mut line, &[Key::KillWordLeft]);
+        assert_eq!(line.text(), "dstcp a.");
+    }
+
+    #[test]
+    fn word_motion_stops_at_punctuation() {
+        let mut line = Line::default();
+        line.set("ls src/main.rs");
+        line.cursor = line.word_left();
+        assert_eq!(line.cursor, 12);
+        line.cursor = line.word_left();
+        assert_eq!(line.cursor, 7);
+        line.cursor = 0;
+        line.cursor = line.word_right();
+        assert_eq!(line.cursor, 2);
+    }
+
+    #[test]
+    fn history_navigation_keeps_the_draft() {
+        let history = ["first".to_string(), "second".to_string()];
+        let mut editor = Editor::new();
+        editor.history_index = history.len();
+        let mut line = Line::default();
+        type_keys(&mut editor, &mut line, &typed("dra"));
+        for key in [Key::Up, Key::Up, Key::Up] {
+            editor.apply(&mut line, key, &history);
+        }
+        assert_eq!(line.text(), "first");
+        editor.apply(&mut line, Key::Down, &history);
+        assert_eq!(line.text(), "second");
+        editor.apply(&mut line, Key::Down, &history);
+        editor.apply(&mut line, Key::Down, &history);
+        assert_eq!(line.text(), "dra");
+    }
+
+    fn search_history() -> Vec<String> {
+        ["make test", "ls src", "make build", "cd src"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn press(editor: &mut Editor, line: &mut Line, history: &[String], keys: &[Key]) -> Outcome {
+        let mut outcome = Outcome::Continue;
+        for &key in keys {
+            outcome = editor.apply(line, key, history);
+        }
+        outcome
+    }
+
+    #[test]
+    fn ctrl_r_searches_back_and_cycles() {
+        let history = search_history();
+        let mut editor = Editor::new();
+        editor.history_index = history.len();
+        let mut line = Line::default();
+        press(
+            &mut editor,
+            &mut line,
+            &history,
+            &[Key::Ctrl('r'), Key::Char('m')],
+        );
+        assert_eq!(line.text(), "make build");
+        assert_eq!(
+            editor.search.as_ref().unwrap().prompt(),
+            "(reverse-i-search)`m': "
+        );
+        press(
+            &mut editor,
+            &mut line,
+            &history,
+            &[Key::Char('a'), Key::Ctrl('r')],
+        );
+        assert_eq!(line.text(), "make test");
+        assert_eq!(line.cursor, 0);
+
+        press(&mut editor, &mut line, &history, &[Key::Ctrl('r')]);
+        assert_eq!(line.text(), "make test");
+        assert!(editor.search.as_ref().unwrap().failed);
+        press(&mut editor, &mut line, &history, &[Key::Ctrl('s')]);
+        assert_eq!(line.text(), "make build");
+    }
+
+    #[test]
+    fn other_keys_end_the_search_and_act_on_the_match() {
+        let history = search_history();
+        let mut editor = Editor::new();
+        editor.history_index = history.len();
+        let mut line = Line::default();
+        let keys = typed("src");
+        press(&mut editor, &mut line, &history, &[Key::Ctrl('r')]);
+        press(&mut editor, &mut line, &history, &keys);
+        assert_eq!((line.text().as_str(), line.cursor), ("cd src", 3));
+        press(
+            &mut editor,
+            &mut line,
+            &history,
+            &[Key::Ctrl('e'), Key::Char('/')],
+        );
+        assert_eq!(line.text(), "cd src/");
+        assert!(editor.search.is_none());
+
+        // Ctrl-R with nothing typed repeats the last search.
+        let outcome = press(
+            &mut editor,
+            &mut line,
+            &history,
+            &[Key::Ctrl('r'), Key::Ctrl('r'), Key::Enter],
+        );
+        assert_eq!(outcome, Outcome::Accept);
+        assert_eq!(line.text(), "ls src");
+    }
+
+    #[test]
+    fn ctrl_g_puts_the_line_back() {
+        let history = search_history();
+        let mut editor = Editor::new();
+        editor.history_index = history.len();
+        let mut line = Line::default();
+        press(&mut editor, &mut line, &history, &typed("ech"));
+        press(
+            &mut editor,
+            &mut line,
+            &history,
+            &[Key::Ctrl('r'), Key::Char('l')],
+        );
+        assert_eq!(line.text(), "make build");
+        press(&mut editor, &mut line, &history, &[Key::Char('x')]);
+        assert!(editor.search.as_ref().unwrap().failed);
+        assert_eq!(
+            editor.search.as_ref().unwrap().prompt(),
+            "(failed reverse-i-search)`lx': "
+        );
+        press(&mut editor, &mut line, &history, &[Key::Ctrl('g')]);
+        assert_eq!(line.text(), "ech");
+        assert_eq!(editor.history_index, history.len());
+    }
+
+    fn completions(start: usize, candidates: &[(&str, &str)]) -> Completions {
+        Completions {
+            start,
+            candidates: candidates
+                .iter()
+                .map(|(replacement, suffix)| crate::completion::Candidate {
+                    replacement: replacement.to_string(),
+                    display: replacement.to_string(),
+                    suffix: suffix.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn tab_completes_one_candidate_or_the_common_prefix() {
+        let mut editor = Editor::new();
+        let mut line = Line::default();
+        line.set("cat sr");
+        let outcome = editor.complete(&mut line, completions(4, &[("src/", "")]));
+        assert_eq!(outcome, TabOutcome::Completed);
+        assert_eq!((line.text().as_str(), line.cursor), ("cat src/", 8));
+
+        line.set("cat src/ma");
+        line.cursor = 8;
+        let found = [("src/main.rs", " "), ("src/map.rs", " ")];
+        assert_eq!(
+            editor.complete(&mut line, completions(4, &found)),
+            TabOutcome::Completed
+        );
+        assert_eq!((line.text().as_str(), line.cursor), ("cat src/mama", 10));
+    }
+
+    #[test]
+    fn second_tab_lists_ambiguous_candidates() {
+        let mut editor = Editor::new();
+        let mut line = Line::default();
+        line.set("cat a");
+        let found = [("ab", " "), ("ac", " ")];
+        assert_eq!(
+            editor.complete(&mut line, completions(4, &found)),
+            TabOutcome::Bell
+        );
+        assert_eq!(
+            editor.complete(&mut line, completions(4, &found)),
+            TabOutcome::List(vec!["ab".to_string(), "ac".to_string()])
+        );
+        assert_eq!(line.text(), "cat a");
+        assert_eq!(
+            editor.complete(&mut line, completions(4, &[])),
+            TabOutcome::Bell
+        );
+    }
+
+    #[test]
+    fn columns_fill_down_then_across() {
+        let items: Vec<String> = ["a", "bb", "c", "dddd", "e"].map(String::from).to_vec();
+        assert_eq!(columns(&items, 20), ["a     c     e", "bb    dddd"]);
+        assert_eq!(columns(&items, 4), ["a", "bb", "c", "dddd", "e"]);
+        let wide: Vec<String> = ["漢字", "x"].map(String::from).to_vec();
+        assert_eq!(columns(&wide, 80), ["漢字  x"]);
+    }
+
+    #[test]
+    fn visible_skips_non_printing_runs() {
+        let prompt = "\x01\x1b[1m\x02漢\x01\x1b[0m\x02> ";
+        assert_eq!(visible(prompt).collect::<String>(), "漢> ");
+        assert_eq!(position(visible(prompt), 80), (0, 4));
+        assert_eq!(position("ab\n$ ".chars(), 80), (1, 2));
+    }
+
+    #[test]
+    fn ctrl_d_only_ends_input_on_an_empty_line() {
+        let mut editor = Editor::new();
+        let mut line = Line::default();
+        type_keys(&mut editor, &mut line, &typed("ab"));
+        type_keys(&mut editor, &mut line, &[Key::Home, Key::Ctrl('d')]);
+        assert_eq!(line.text(), "b");
+        type_keys(&mut editor, &mut line, &[Key::Ctrl('d')]);
+        assert_eq!(
+            editor.apply(&mut line, Key::Ctrl('d'), &[]),
+            Outcome::EndOfInput
+        );
+    }
+
+    #[test]
+    fn char_width_counts_wide_and_combining_characters() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('漢'), 2);
+        assert_eq!(char_width('😀'), 2);
+        assert_eq!(char_width('\u{301}'), 0);
+    }
+
+    #[test]
+    fn position_wraps_rows_by_display_width() {
+        assert_eq!(position("$ abc".chars(), 10), (0, 5));
+        assert_eq!(position("0123456789".chars(), 10), (1, 0));
+        assert_eq!(position("漢字漢字漢".chars(), 10), (1, 0));
+        // A wide character that would straddle the edge moves down whole.
+        assert_eq!(position("012345678漢".chars(), 10), (1, 2));
+        assert_eq!(position("e\u{301}".chars(), 10), (0, 1));
+    }
+}
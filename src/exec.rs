@@ -0,0 +1,599 @@
+use crate::brace;
+use crate::builtins::{execute_command, is_builtin};
+use crate::expand;
+use crate::external;
+use crate::glob::{self, GlobOptions};
+use crate::history::History;
+use crate::interrupt;
+use crate::parser::{parse_list, tokenize, AndOrList, Connector, Pipeline, Redirect, RedirectKind};
+use crate::redirect::FdTable;
+use crate::vars::Variables;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, PipeReader, Read, Write};
+use std::process::Child;
+use std::thread::{self, JoinHandle};
+
+// State that lives for the whole shell session. Pipeline stages that run
+// on their own thread get a clone, so changes they make (like `exit` or
+// setting a variable) stay local to that stage, the way a subshell's
+// would. The working directory belongs to the whole process, though, so
+// such a stage's `cd` doesn't change it (see `threaded`).
+#[derive(Clone, Default)]
+pub struct Shell {
+    pub vars: Variables,
+    pub glob_options: GlobOptions,
+    // Exit status of the most recent pipeline, as reported by `$?`.
+    pub last_status: i32,
+    // Exit status of the last command substitution run while expanding the
+    // current pipeline, which is what a command made up only of
+    // assignments (`x=$(cmd)`) reports.
+    pub substitution_status: Option<i32>,
+    // Set by the `exit` built-in; the REPL exits with it once the current
+    // command finishes.
+    pub exit_status: Option<i32>,
+    pub history: History,
+    // `$0`: the script being run, or the shell itself when interactive.
+    pub name: String,
+    // The positional parameters `$1`, `$2`, ... from the command line.
+    pub positional: Vec<String>,
+    // Set on the copy a built-in gets when it runs on its own thread as
+    // part of a longer pipeline. `cd` there only checks its directory,
+    // since the real one would change for every stage and the shell too.
+    pub threaded: bool,
+    // Whether commands are being read from a terminal. A shell that isn't
+    // exits on some errors an interactive one just reports.
+    pub interactive: bool,
+}
+
+impl Shell {
+    // A fresh session, with the process environment as its exported
+    // variables.
+    pub fn new() -> Shell {
+        Shell {
+            vars: Variables::from_env(),
+            ..Shell::default()
+        }
+    }
+}
+
+// Applies `NAME=value` assignments to `vars`, reporting any that fail
+// because the variable is readonly. Returns the resulting exit status.
+fn assign(assignments: &[(String, String)], vars: &mut Variables, fds: &FdTable) -> i32 {
+    let mut status = 0;
+    for (name, value) in assignments {
+        if let Err(e) = vars.set(name, value) {
+            fds.report(&e);
+            status = 1;
+        }
+    }
+    status
+}
+
+// A simple command after expansion, ready to run. Its words are
+// `OsString`s because glob matches are filenames, which needn't be UTF-8.
+struct ExpandedCommand {
+    assignments: Vec<(String, String)>,
+    words: Vec<OsString>,
+    redirects: Vec<Redirect>,
+}
+
+// A pipeline stage that has been started, or one that has already finished
+// (a lone built-in, or a stage that failed to start at all).
+enum RunningStage {
+    Builtin(JoinHandle<i32>),
+    External(String, Child),
+    Finished(i32),
+}
+
+// Runs a built-in with the streams from `fds`, flushing its output when
+// it's done. One that Ctrl-C cut short reports 130, as if it had been a
+// program killed by SIGINT.
+fn run_builtin(command: &str, args: &[OsString], fds: &FdTable, shell: &mut Shell) -> i32 {
+    let args: Vec<&OsStr> = args.iter().map(OsString::as_os_str).collect();
+    let (mut input, mut output, mut error) = fds.builtin_streams();
+    let status = execute_command(
+        command,
+        &args,
+        input.as_mut().map(|r| r as &mut dyn Read),
+        &mut output,
+        &mut error,
+        shell,
+    );
+    let _ = output.flush();
+    if interrupt::interrupted() {
+        130
+    } else {
+        status
+    }
+}
+
+// Runs a pipeline of one or more stages connected by OS pipes. Every stage
+// runs concurrently, so output streams through as it's produced rather than
+// being collected first: external programs as child processes, and built-ins
+// on their own threads (each with a copy of the shell state, the way a real
+// shell runs pipeline stages in subshells). A lone built-in runs directly on
+// the shell's own state, so e.g. `cd` still takes effect. Each stage starts
+// with the descriptors in `base` (normally the shell's stdin/stdout/stderr),
+// swaps in the pipes to its neighbours, and then applies its own
+// redirections in the order they were written. A stage with only
+// redirections (e.g. `> file`) just applies them. Returns the exit status
+// of the last stage, like a POSIX shell.
+// While a longer pipeline runs, SIGINT is only let through to the first
+// stage's thread: a signal goes to just one thread, and it's the first
+// stage that would be blocked reading the terminal, with the stages after
+// it waiting for that one to finish.
+fn run_pipeline(commands: &[ExpandedCommand], shell: &mut Shell, base: &FdTable) -> i32 {
+    let last_index = commands.len() - 1;
+    interrupt::clear();
+    if last_index > 0 {
+        interrupt::block(true);
+    }
+    let mut piped_input: Option<PipeReader> = None;
+    let mut running = Vec::new();
+
+    for (i, stage) in commands.iter().enumerate() {
+        let mut fds = match base.try_clone() {
+            Ok(fds) => fds,
+            Err(e) => {
+                base.report(&format!("dup: {e}"));
+                running.push(RunningStage::Finished(1));
+                break;
+            }
+        };
+        if let Some(reader) = piped_input.take() {
+            fds.set(0, reader);
+        }
+        if i != last_index {
+            match io::pipe() {
+                Ok((reader, writer)) => {
+                    piped_input = Some(reader);
+                    fds.set(1, writer);
+                }
+                Err(e) => {
+                    fds.report(&format!("pipe: {e}"));
+                    running.push(RunningStage::Finished(1));
+                    break;
+                }
+            }
+        }
+
+        // A stage whose redirections fail doesn't run, but the rest of the
+        // pipeline still does (its neighbours just see a closed pipe).
+        if let Err(e) = fds.apply_all(&stage.redirects) {
+            fds.report(&e);
+            running.push(RunningStage::Finished(1));
+            continue;
+        }
+
+        // Assignments on their own set shell variables (in the stage's own
+        // copy of the shell, when it's part of a longer pipeline).
+        let Some((command, args)) = stage.words.split_first() else {
+            let status = if commands.len() == 1 {
+                match assign(&stage.assignments, &mut shell.vars, &fds) {
+                    0 => shell.substitution_status.unwrap_or(0),
+                    status => status,
+                }
+            } else {
+                assign(&stage.assignments, &mut shell.vars.clone(), &fds)
+            };
+            running.push(RunningStage::Finished(status));
+            continue;
+        };
+        let args = args.to_vec();
+
+        // Assignments in front of a command only apply to that command: an
+        // external program gets them in its environment, and a built-in sees
+        // them while it runs.
+        let Some(builtin) = command.to_str().filter(|command| is_builtin(command)) else {
+            let mut env = shell.vars.clone();
+            if assign(&stage.assignments, &mut env, &fds) != 0 {
+                running.push(RunningStage::Finished(1));
+                continue;
+            }
+            for (name, _) in &stage.assignments {
+                env.export(name);
+            }
+            running.push(match external::spawn_external(command, &args, &fds, &env) {
+                Ok(child) => RunningStage::External(command.to_string_lossy().into_owned(), child),
+                Err(status) => RunningStage::Finished(status),
+            });
+            continue;
+        };
+        let command = builtin.to_string();
+        if commands.len() == 1 {
+            let saved = shell.vars.clone();
+            let mut status = assign(&stage.assignments, &mut shell.vars, &fds);
+            if status == 0 {
+                status = run_builtin(&command, &args, &fds, shell);
+            }
+            for (name, _) in &stage.assignments {
+                shell.vars.restore_from(&saved, name);
+            }
+            running.push(RunningStage::Finished(status));
+        } else {
+            let mut stage_shell = Shell {
+                threaded: true,
+                ..shell.clone()
+            };
+            if assign(&stage.assignments, &mut stage_shell.vars, &fds) != 0 {
+                running.push(RunningStage::Finished(1));
+                continue;
+            }
+            let handle = thread::spawn(move || {
+                if i == 0 {
+                    interrupt::block(false);
+                }
+                run_builtin(&command, &args, &fds, &mut stage_shell)
+            });
+            running.push(RunningStage::Builtin(handle));
+        }
+    }
+
+    let mut status = 0;
+    for stage in running {
+        status = match stage {
+            RunningStage::Builtin(handle) => handle.join().unwrap_or(1),
+            RunningStage::External(command, child) => external::wait_external(&command, child),
+            RunningStage::Finished(status) => status,
+        };
+    }
+    if last_index > 0 {
+        interrupt::block(false);
+    }
+    status
+}
+
+fn expand_redirect(redirect: &Redirect, shell: &mut Shell) -> Result<Redirect, String> {
+    let mut expand = |word: &str| expand::expand_word(word, shell);
+    let kind = match &redirect.kind {
+        RedirectKind::Overwrite(filename) => RedirectKind::Overwrite(expand(filename)?),
+        RedirectKind::Append(filename) => RedirectKind::Append(expand(filename)?),
+        RedirectKind::Input(filename) => RedirectKind::Input(expand(filename)?),
+        RedirectKind::HereString(word) => RedirectKind::HereString(expand(word)?),
+        RedirectKind::HereDoc(heredoc) => {
+            let mut heredoc = heredoc.clone();
+            if heredoc.expand {
+                heredoc.body = expand::expand_heredoc(&heredoc.body, shell)?;
+            }
+            RedirectKind::HereDoc(heredoc)
+        }
+        kind @ (RedirectKind::Dup(_) | RedirectKind::Close) => kind.clone(),
+    };
+    Ok(Redirect {
+        fd: redirect.fd,
+        kind,
+    })
+}
+
+// The expression in a `((...))` arithmetic command, which runs as `let`
+// with the expression as its one argument.
+fn arithmetic_command(words: &[String]) -> Option<&str> {
+    match words {
+        [word] => word.strip_prefix("((")?.strip_suffix("))"),
+        _ => None,
+    }
+}
+
+// Expands braces, parameters and globs in a pipeline's words (and parameters
+// in its assignments and redirections) just before it runs, so each pipeline
+// in a list sees the variables and status left by the ones before it. Fails
+// if a parameter expansion does, or if a pattern matches nothing under
+// failglob.
+fn expand_pipeline(pipeline: &Pipeline, shell: &mut Shell) -> Result<Vec<ExpandedCommand>, String> {
+    shell.substitution_status = None;
+    let mut commands = Vec::new();
+    for command in &pipeline.commands {
+        let mut assignments = Vec::new();
+        for (name, value) in &command.assignments {
+            assignments.push((name.clone(), expand::expand_assignment(value, shell)?));
+        }
+        let words = match arithmetic_command(&command.words) {
+            Some(expr) => vec!["let".into(), expand::expand_expression(expr, shell)?.into()],
+            None => glob::expand_all(
+                &expand::expand_words(&brace::expand_all(&command.words), shell)?,
+                &shell.glob_options,
+            )?,
+        };
+        let redirects = command
+            .redirects
+            .iter()
+            .map(|redirect| expand_redirect(redirect, shell))
+            .collect::<Result<_, _>>()?;
+        commands.push(ExpandedCommand {
+            assignments,
+            words,
+            redirects,
+        });
+    }
+    Ok(commands)
+}
+
+// A pipeline whose expansion fails (e.g. `${NAME:?}` with NAME unset)
+// doesn't run at all. If the failure also ended the shell, its exit status
+// is the pipeline's.
+fn run_expanded_pipeline(pipeline: &Pipeline, shell: &mut Shell, base: &FdTable) -> i32 {
+    match expand_pipeline(pipeline, shell) {
+        Ok(commands) => run_pipeline(&commands, shell, base),
+        Err(e) => {
+            eprintln!("{e}");
+            shell.exit_status.unwrap_or(1)
+        }
+    }
+}
+
+// Runs each and-or list of a command line in turn. Within an and-or list,
+// a pipeline after `&&` only runs if the status so far is zero, and one
+// after `||` only if it's non-zero; a skipped pipeline leaves the status
+// unchanged, so `false && a || b` still runs `b`. Stops early once `exit`
+// has been run.
+pub fn run_list(list: &[AndOrList], shell: &mut Shell) {
+    run_list_with(list, shell, &FdTable::inherited());
+}
+
+// `run_list`, with every command starting out from the descriptors in
+// `base`.
+fn run_list_with(list: &[AndOrList], shell: &mut Shell, base: &FdTable) {
+    for and_or in list {
+        shell.last_status = run_expanded_pipeline(&and_or.first, shell, base);
+        for (connector, pipeline) in &and_or.rest {
+            if shell.exit_status.is_some() {
+                return;
+            }
+            let should_run = match connector {
+                Connector::And => shell.last_status == 0,
+                Connector::Or => shell.last_status != 0,
+            };
+            if should_run {
+                shell.last_status = run_expanded_pipeline(pipeline, shell, base);
+            }
+        }
+        if shell.exit_status.is_some() {
+            return;
+        }
+    }
+}
+
+// Runs the command line `source` for a command substitution and returns
+// what it wrote to stdout, minus any trailing newlines. It runs on a copy
+// of the shell, the way a subshell would, so variables it sets and an
+// `exit` in it stay inside it; only its exit status comes back, as `$?`.
+// The working directory is shared by the whole process, so it's put back
+// afterwards in case the commands changed it. The output is read on its
+// own thread while the commands run, so they can't block on a full pipe.
+pub fn command_output(source: &str, shell: &mut Shell) -> Result<String, String> {
+    let list = parse_list(&tokenize(source)?)?;
+    let (mut reader, writer) = io::pipe().map_err(|e| format!("pipe: {e}"))?;
+    let collector = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = reader.read_to_end(&mut output);
+        output
+    });
+
+    let mut base = FdTable::inherited();
+    base.set(1, writer);
+    let mut subshell = shell.clone();
+    let cwd = env::current_dir();
+    run_list_with(&list, &mut subshell, &base);
+    if let Ok(cwd) = cwd {
+        let _ = env::set_current_dir(cwd);
+    }
+    drop(base);
+    shell.last_status = subshell.last_status;
+    shell.substitution_status = Some(subshell.last_status);
+
+    let output = collector.join().unwrap_or_default();
+    let output = String::from_utf8_lossy(&output);
+    Ok(output.trim_end_matches('\n').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "zero_shell_exec_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Runs `source` with its stdout going into a pipe, and returns what
+    // came out of the other end.
+    fn run(source: &str, shell: &mut Shell) -> Vec<u8> {
+        let list = parse_list(&tokenize(source).unwrap()).unwrap();
+        let (mut reader, writer) = io::pipe().unwrap();
+        let collector = thread::spawn(move || {
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            output
+        });
+        let mut base = FdTable::inherited();
+        base.set(1, writer);
+        run_list_with(&list, shell, &base);
+        drop(base);
+        collector.join().unwrap()
+    }
+
+    #[test]
+    fn and_or_lists_skip_what_isnt_needed() {
+        let mut shell = Shell::new();
+        assert_eq!(run("true && echo a || echo b", &mut shell), b"a\n");
+        assert_eq!(run("false && echo a || echo b", &mut shell), b"b\n");
+        assert_eq!(run("false || false || echo c", &mut shell), b"c\n");
+        assert_eq!(run("true || echo d; echo e", &mut shell), b"e\n");
+        assert_eq!(shell.last_status, 0);
+    }
+
+    #[test]
+    fn skipped_commands_leave_the_status_alone() {
+        let mut shell = Shell::new();
+        run("false && echo never", &mut shell);
+        assert_eq!(shell.last_status, 1);
+        assert_eq!(run("sh -c 'exit 4' || true && echo $?", &mut shell), b"0\n");
+        assert_eq!(run("sh -c 'exit 4' && true; echo $?", &mut shell), b"4\n");
+    }
+
+    #[test]
+    fn exit_stops_the_rest_of_the_list() {
+        let mut shell = Shell::new();
+        assert_eq!(run("echo a; exit 2 && echo b; echo c", &mut shell), b"a\n");
+        assert_eq!(shell.exit_status, Some(2));
+    }
+
+    #[test]
+    fn pipeline_stages_run_at_the_same_time() {
+        let mut shell = Shell::new();
+        let start = Instant::now();
+        run("sleep 0.5 | sleep 0.5 | sleep 0.5", &mut shell);
+        assert!(start.elapsed() < Duration::from_millis(1400));
+        assert_eq!(shell.last_status, 0);
+    }
+
+    #[test]
+    fn pipeline_ends_when_the_reader_stops() {
+        let mut shell = Shell::new();
+        assert_eq!(run("yes | head -n 1", &mut shell), b"y\n");
+        assert_eq!(shell.last_status, 0);
+    }
+
+    #[test]
+    fn pipeline_passes_binary_data_through() {
+        let dir = temp_dir("binary");
+        let file = dir.join("bytes");
+        let bytes: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        fs::write(&file, &bytes).unwrap();
+        let mut shell = Shell::new();
+        let source = format!("cat {} | cat | /bin/cat", file.display());
+        assert_eq!(run(&source, &mut shell), bytes);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_matches_reach_commands_byte_for_byte() {
+        let dir = temp_dir("non_utf8");
+        let name = OsStr::from_bytes(b"bad\xffname");
+        fs::write(dir.join(name), b"x\n").unwrap();
+        let mut shell = Shell::new();
+        let pattern = format!("{}/ba*", dir.display());
+        let mut expected = dir.join(name).into_os_string().into_vec();
+        expected.push(b'\n');
+        assert_eq!(run(&format!("echo {pattern}"), &mut shell), expected);
+        assert_eq!(run(&format!("/bin/cat {pattern}"), &mut shell), b"x\n");
+        run(&format!("rm {pattern}"), &mut shell);
+        assert_eq!(shell.last_status, 0);
+        assert!(!dir.join(name).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pipeline_status_is_the_last_stages() {
+        let mut shell = Shell::new();
+        run("true | false", &mut shell);
+        assert_eq!(shell.last_status, 1);
+        run("false | true", &mut shell);
+        assert_eq!(shell.last_status, 0);
+        run("exit 3 | sh -c 'exit 5'", &mut shell);
+        assert_eq!(shell.last_status, 5);
+        assert_eq!(shell.exit_status, None);
+    }
+
+    #[test]
+    fn pipeline_stages_keep_changes_to_themselves() {
+        let mut shell = Shell::new();
+        let cwd = env::current_dir().unwrap();
+        let output = run("X=1 | echo $X; cd / | pwd", &mut shell);
+        assert_eq!(output, format!("\n{}\n", cwd.display()).into_bytes());
+        assert_eq!(env::current_dir().unwrap(), cwd);
+        assert_eq!(shell.vars.get("X"), None);
+        run("cd / | cd /zero_shell_missing 2>/dev/null", &mut shell);
+        assert_eq!(shell.last_status, 1);
+        assert_eq!(env::current_dir().unwrap(), cwd);
+    }
+
+    #[test]
+    fn unset_parameter_errors_end_a_non_interactive_shell() {
+        let source = "echo ${U:?boom} || echo alt; echo still";
+        let mut shell = Shell::new();
+        assert_eq!(run(source, &mut shell), b"");
+        assert_eq!(shell.exit_status, Some(127));
+
+        // A command substitution runs in a subshell, so only that exits.
+        let mut shell = Shell::new();
+        let output = run("echo $(echo ${U:?boom}; echo no) $?", &mut shell);
+        assert_eq!(output, b"127\n");
+        assert_eq!(shell.exit_status, None);
+
+        let mut shell = Shell {
+            interactive: true,
+            ..Shell::new()
+        };
+        assert_eq!(run(source, &mut shell), b"alt\nstill\n");
+        assert_eq!(shell.exit_status, None);
+    }
+
+    // Runs the ignored test `name` by itself in a new copy of the test
+    // binary, for checks that change how the whole process handles
+    // signals and so would disturb the tests running alongside them.
+    fn run_alone(name: &str) {
+        let output = std::process::Command::new(env::current_exe().unwrap())
+            .args(["--exact", name, "--ignored", "--test-threads=1"])
+            .output()
+            .unwrap();
+        let report = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success() && report.contains("1 passed"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn interrupted_builtins_report_130() {
+        run_alone("exec::tests::ctrl_c_stops_a_builtin_waiting_for_input");
+    }
+
+    #[test]
+    #[ignore = "catches SIGINT for the whole process; run by interrupted_builtins_report_130"]
+    fn ctrl_c_stops_a_builtin_waiting_for_input() {
+        interrupt::catch();
+        // Nothing is written to `cat`'s input, so only Ctrl-C (here, SIGINT
+        // sent to this thread until it gets through) can end it. If that
+        // never works, the input is closed after a while so the test fails
+        // rather than hanging.
+        let (input, unused) = io::pipe().unwrap();
+        let (mut reader, writer) = io::pipe().unwrap();
+        let mut base = FdTable::inherited();
+        base.set(0, input);
+        base.set(1, writer);
+        let this_thread = unsafe { libc::pthread_self() };
+        let (done, finished) = std::sync::mpsc::channel::<()>();
+        let signaller = thread::spawn(move || {
+            let start = Instant::now();
+            while finished.recv_timeout(Duration::from_millis(20)).is_err() {
+                if start.elapsed() > Duration::from_secs(5) {
+                    drop(unused);
+                    return;
+                }
+                unsafe { libc::pthread_kill(this_thread, libc::SIGINT) };
+            }
+        });
+
+        let mut shell = Shell::new();
+        let list = parse_list(&tokenize("cat; echo $?").unwrap()).unwrap();
+        run_list_with(&list, &mut shell, &base);
+        done.send(()).unwrap();
+        signaller.join().unwrap();
+        drop(base);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "130\n");
+    }
+}
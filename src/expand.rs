@@ -0,0 +1,1021 @@
+use crate::arith;
+use crate::brace::unquoted_mask;
+use crate::exec::{command_output, Shell};
+use crate::glob::wildcard_matches;
+use crate::parser::{backtick_end, matching_brace, substitution_end};
+use crate::users::get_home_dir_by_name;
+use crate::vars::is_valid_name;
+
+// Stands between the positional parameters in the value of `$@`, so that
+// `"$@"` can still come out as one field per parameter. It can't turn up
+// in a real argument, since those can't contain NUL bytes.
+const FIELD_BREAK: char = '\0';
+
+// Whether `name` can follow a `$`: a variable name, a positional parameter
+// (`0`, `1`, `10`, ...) or one of the special parameters `?`, `#`, `@`
+// and `*`.
+fn is_parameter(name: &str) -> bool {
+    matches!(name, "?" | "#" | "@" | "*")
+        || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+        || is_valid_name(name)
+}
+
+// The value of a parameter, or None if it's unset. `$@` and `$*` count as
+// unset when there are no positional parameters.
+fn lookup(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        "@" | "*" if shell.positional.is_empty() => None,
+        "@" => Some(shell.positional.join(&FIELD_BREAK.to_string())),
+        // `$*` joins them with the first character of $IFS.
+        "*" => {
+            let ifs = shell.vars.get("IFS").unwrap_or(" ");
+            let separator: String = ifs.chars().take(1).collect();
+            Some(shell.positional.join(&separator))
+        }
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => match name.parse::<usize>() {
+            Ok(0) => Some(shell.name.clone()),
+            Ok(n) => shell.positional.get(n - 1).cloned(),
+            Err(_) => None,
+        },
+        _ => shell.vars.get(name).map(str::to_string),
+    }
+}
+
+// Cuts the shortest (or longest) suffix matching the glob `pattern` off
+// `value`, or returns it unchanged if no suffix matches.
+fn remove_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut starts: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    starts.push(value.len());
+    if !longest {
+        starts.reverse();
+    }
+    starts
+        .into_iter()
+        .find(|&start| wildcard_matches(pattern, &value[start..]))
+        .map_or_else(|| value.to_string(), |start| value[..start].to_string())
+}
+
+// The prefix counterpart of `remove_suffix`.
+fn remove_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut ends: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    ends.push(value.len());
+    if longest {
+        ends.reverse();
+    }
+    ends.into_iter()
+        .find(|&end| wildcard_matches(pattern, &value[..end]))
+        .map_or_else(|| value.to_string(), |end| value[end..].to_string())
+}
+
+// Expands the inside of a `${...}`: a parameter, optionally preceded by
+// `#` (length) or followed by one of the POSIX modifiers. The word after a
+// modifier is only expanded if it's actually used.
+fn expand_braced(inner: &str, shell: &mut Shell) -> Result<String, String> {
+    let bad_substitution = || format!("${{{inner}}}: bad substitution");
+
+    if let Some(name) = inner.strip_prefix('#').filter(|name| !name.is_empty()) {
+        if !is_parameter(name) {
+            return Err(bad_substitution());
+        }
+        // The "length" of `$@` or `$*` is the number of parameters.
+        if name == "@" || name == "*" {
+            return Ok(shell.positional.len().to_string());
+        }
+        let length = lookup(name, shell).unwrap_or_default().chars().count();
+        return Ok(length.to_string());
+    }
+
+    let name_len = if inner.starts_with(['?', '#', '@', '*']) {
+        1
+    } else if inner.starts_with(|c: char| c.is_ascii_digit()) {
+        inner
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(inner.len())
+    } else {
+        inner
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(inner.len())
+    };
+    let (name, modifier) = inner.split_at(name_len);
+    if !is_parameter(name) {
+        return Err(bad_substitution());
+    }
+    let value = lookup(name, shell);
+    if modifier.is_empty() {
+        return Ok(value.unwrap_or_default());
+    }
+
+    for (op, longest) in [("%%", true), ("%", false), ("##", true), ("#", false)] {
+        if let Some(pattern) = modifier.strip_prefix(op) {
+            let value = value.unwrap_or_default();
+            let pattern = expand_quoted(pattern, Mode::Word, shell)?.pattern();
+            return Ok(if op.starts_with('%') {
+                remove_suffix(&value, &pattern, longest)
+            } else {
+                remove_prefix(&value, &pattern, longest)
+            });
+        }
+    }
+
+    // With a colon, an empty value counts as unset too.
+    let (check_null, modifier) = match modifier.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, modifier),
+    };
+    let mut chars = modifier.chars();
+    let op = chars.next().ok_or_else(bad_substitution)?;
+    let word = chars.as_str();
+    let is_set = value
+        .as_ref()
+        .is_some_and(|value| !(check_null && value.is_empty()));
+
+    match op {
+        '-' if is_set => Ok(value.unwrap_or_default()),
+        '-' => expand_word(word, shell),
+        '=' if is_set => Ok(value.unwrap_or_default()),
+        '=' => {
+            if !is_valid_name(name) {
+                return Err(format!("${name}: cannot assign in this way"));
+            }
+            let word = expand_word(word, shell)?;
+            shell.vars.set(name, &word)?;
+            Ok(word)
+        }
+        '?' if is_set => Ok(value.unwrap_or_default()),
+        '?' => {
+            // POSIX has a shell that isn't interactive exit here; 127 is
+            // the status bash exits with.
+            if !shell.interactive {
+                shell.exit_status = Some(127);
+            }
+            let message = expand_word(word, shell)?;
+            if message.is_empty() {
+                Err(format!("{name}: parameter null or not set"))
+            } else {
+                Err(format!("{name}: {message}"))
+            }
+        }
+        '+' if is_set => expand_word(word, shell),
+        '+' => Ok(String::new()),
+        _ => Err(bad_substitution()),
+    }
+}
+
+// Expands the parameter starting at the `$` at `chars[i]` onto the end of
+// `expanded`: `$NAME`, a special parameter (`$?`, `$#`, `$@`, `$*`), a
+// single-digit positional parameter (`$0` to `$9`; `${10}` needs the
+// braces), a `${...}` form, a `$((...))` arithmetic expansion or a `$(...)`
+// command substitution. A `$` that doesn't start one of these is kept as
+// it is.
+// Returns the index just past what was used.
+fn expand_parameter(
+    chars: &[char],
+    i: usize,
+    expanded: &mut String,
+    shell: &mut Shell,
+) -> Result<usize, String> {
+    match chars.get(i + 1) {
+        Some(&c) if matches!(c, '?' | '#' | '@' | '*') || c.is_ascii_digit() => {
+            expanded.push_str(&lookup(&c.to_string(), shell).unwrap_or_default());
+            Ok(i + 2)
+        }
+        Some('{') => {
+            let end = matching_brace(chars, i + 2).ok_or_else(|| {
+                let text: String = chars[i..].iter().collect();
+                format!("{text}: bad substitution")
+            })?;
+            let inner: String = chars[i + 2..end].iter().collect();
+            expanded.push_str(&expand_braced(&inner, shell)?);
+            Ok(end + 1)
+        }
+        Some('(') => {
+            let end = substitution_end(chars, i + 2)
+                .ok_or("syntax error: unterminated $( command substitution")?;
+            if chars[i + 2] == '(' && end > i + 3 && chars[end - 1] == ')' {
+                let expr: String = chars[i + 3..end - 1].iter().collect();
+                let expr = expand_expression(&expr, shell)?;
+                expanded.push_str(&arith::evaluate(&expr, &mut shell.vars)?.to_string());
+                return Ok(end + 1);
+            }
+            let command: String = chars[i + 2..end].iter().collect();
+            expanded.push_str(&command_output(&command, shell)?);
+            Ok(end + 1)
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            let start = i + 1;
+            let mut end = start;
+            while chars
+                .get(end)
+                .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                end += 1;
+            }
+            let name: String = chars[start..end].iter().collect();
+            expanded.push_str(shell.vars.get(&name).unwrap_or_default());
+            Ok(end)
+        }
+        _ => {
+            expanded.push('$');
+            Ok(i + 1)
+        }
+    }
+}
+
+// Runs the `` `...` `` command substitution starting at the backtick at
+// `chars[i]`, appending its output to `expanded`. Inside backticks a
+// backslash only escapes `$`, `` ` `` and `\` (and `"` within double
+// quotes); those are unescaped before the command is run. Returns the index
+// just past the closing backtick.
+fn expand_backticks(
+    chars: &[char],
+    i: usize,
+    in_double: bool,
+    expanded: &mut String,
+    shell: &mut Shell,
+) -> Result<usize, String> {
+    let end = backtick_end(chars, i + 1).ok_or("syntax error: unterminated ` quote")?;
+    let mut command = String::new();
+    let mut j = i + 1;
+    while j < end {
+        let escaped = chars
+            .get(j + 1)
+            .filter(|&&c| matches!(c, '$' | '`' | '\\') || (c == '"' && in_double));
+        match escaped {
+            Some(&c) if chars[j] == '\\' => {
+                command.push(c);
+                j += 2;
+            }
+            _ => {
+                command.push(chars[j]);
+                j += 1;
+            }
+        }
+    }
+    expanded.push_str(&command_output(&command, shell)?);
+    Ok(end + 1)
+}
+
+// Decodes a `$'...'` string whose contents start at `chars[start]`,
+// returning the text and the index just past the closing quote. Supports
+// the usual C escapes plus `\e`, octal `\nnn`, hex `\xHH` and Unicode
+// `\uHHHH`/`\UHHHHHHHH`; any other backslash is kept as it is.
+fn ansi_c_quoted(chars: &[char], start: usize) -> (String, usize) {
+    let mut text = String::new();
+    let mut i = start;
+    while i < chars.len() && chars[i] != '\'' {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            text.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let escape = chars[i + 1];
+        i += 2;
+        let simple = match escape {
+            'a' => Some('\x07'),
+            'b' => Some('\x08'),
+            'e' | 'E' => Some('\x1b'),
+            'f' => Some('\x0c'),
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            'v' => Some('\x0b'),
+            '\\' | '\'' | '"' | '?' => Some(escape),
+            _ => None,
+        };
+        if let Some(c) = simple {
+            text.push(c);
+            continue;
+        }
+        let (radix, max_digits) = match escape {
+            '0'..='7' => (8, 3),
+            'x' => (16, 2),
+            'u' => (16, 4),
+            'U' => (16, 8),
+            _ => {
+                text.push('\\');
+                text.push(escape);
+                continue;
+            }
+        };
+        // An octal escape's first digit is the escape character itself.
+        if radix == 8 {
+            i -= 1;
+        }
+        let digits: String = chars[i..]
+            .iter()
+            .take(max_digits)
+            .take_while(|c| c.is_digit(radix))
+            .collect();
+        i += digits.len();
+        match u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(c) => text.push(c),
+            None => {
+                text.push('\\');
+                text.push(escape);
+            }
+        }
+    }
+    (text, i + 1)
+}
+
+// Whether the text being processed is an ordinary word or the body of a
+// here-document, where quote characters are just text but `$` and
+// backslashes still work as they do inside double quotes.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Word,
+    HereDoc,
+}
+
+// Expands the `$` or backtick at the given index onto the end of a string,
+// returning the index just past it. The flag says whether it's inside
+// double quotes.
+type OnExpansion<'a> = dyn FnMut(&[char], usize, bool, &mut String) -> Result<usize, String> + 'a;
+
+// A word after quote removal, remembering which of its characters came
+// from quoted (or backslash-escaped) text, since only unquoted wildcards
+// count when it's used as a glob pattern, and which came from an unquoted
+// command substitution, since only those are split into separate fields.
+#[derive(Default)]
+struct Word {
+    text: String,
+    quoted: Vec<bool>,
+    split: Vec<bool>,
+    // Where `$@` separates one parameter from the next: a field always
+    // ends there, whether or not it was quoted.
+    breaks: Vec<bool>,
+    // Whether any part of the word was quoted, even an empty `""`.
+    has_quotes: bool,
+}
+
+impl Word {
+    fn push(&mut self, c: char, quoted: bool) {
+        self.text.push(c);
+        self.quoted.push(quoted);
+        self.split.push(false);
+        self.breaks.push(false);
+    }
+
+    // Ends a field of a `$@` expansion, with a space standing in for the
+    // break wherever the word is used whole.
+    fn push_break(&mut self, quoted: bool) {
+        self.push(' ', quoted);
+        *self.breaks.last_mut().unwrap() = true;
+    }
+
+    fn push_str(&mut self, s: &str, quoted: bool) {
+        for c in s.chars() {
+            self.push(c, quoted);
+        }
+    }
+
+    // Appends the output of an unquoted command substitution.
+    fn push_splittable(&mut self, s: &str) {
+        for c in s.chars() {
+            self.push(c, false);
+            *self.split.last_mut().unwrap() = true;
+        }
+    }
+
+    // Splits the word into fields wherever a splittable character is in
+    // `ifs`, and at every `$@` break. As in POSIX shells, a run of IFS
+    // whitespace is one separator and is ignored at either end, while any
+    // other IFS character ends a field every time, even an empty one.
+    fn fields(self, ifs: &str) -> Vec<Word> {
+        let chars: Vec<char> = self.text.chars().collect();
+        let is_separator = |i: usize| self.split[i] && ifs.contains(chars[i]);
+        if !(0..chars.len()).any(|i| self.breaks[i] || is_separator(i)) {
+            return vec![self];
+        }
+
+        let mut fields = Vec::new();
+        let mut field = Word::default();
+        let mut i = 0;
+        while i < chars.len() {
+            if self.breaks[i] {
+                // Inside quotes, an empty parameter is still a field.
+                field.has_quotes |= self.quoted[i];
+                if !field.text.is_empty() || field.has_quotes {
+                    fields.push(std::mem::take(&mut field));
+                }
+                field.has_quotes = self.quoted[i];
+                i += 1;
+                continue;
+            }
+            if !is_separator(i) {
+                field.push(chars[i], self.quoted[i]);
+                field.has_quotes |= self.quoted[i];
+                i += 1;
+                continue;
+            }
+            let mut hard = false;
+            while i < chars.len() && is_separator(i) && (chars[i].is_whitespace() || !hard) {
+                hard |= !chars[i].is_whitespace();
+                i += 1;
+            }
+            if hard || !field.text.is_empty() || field.has_quotes {
+                // An empty field between two hard separators still counts.
+                field.has_quotes |= hard;
+                fields.push(std::mem::take(&mut field));
+            }
+        }
+        if !field.text.is_empty() || field.has_quotes {
+            fields.push(field);
+        }
+        fields
+    }
+
+    // The word as a glob pattern: quoted characters that mean something
+    // in a pattern, and every backslash, are escaped with a backslash so
+    // they only match themselves.
+    fn pattern(&self) -> String {
+        let mut pattern = String::new();
+        for (c, &quoted) in self.text.chars().zip(&self.quoted) {
+            if c == '\\' || (quoted && matches!(c, '*' | '?' | '[' | ']' | '!' | '^' | '-')) {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern
+    }
+}
+
+// Removes quotes and backslash escapes from `word` following POSIX rules,
+// handing each `$` and backtick that isn't quoted away to `on_expansion`.
+fn process_word(word: &str, mode: Mode, on_expansion: &mut OnExpansion) -> Result<Word, String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut result = Word::default();
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\'' if mode == Mode::Word && !in_double => {
+                let close = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == '\'')
+                    .map_or(chars.len(), |pos| i + 1 + pos);
+                for &c in &chars[i + 1..close] {
+                    result.push(c, true);
+                }
+                result.has_quotes = true;
+                i = close + 1;
+            }
+            '"' if mode == Mode::Word => {
+                in_double = !in_double;
+                result.has_quotes = true;
+                i += 1;
+            }
+            '\\' => {
+                let next = chars.get(i + 1).copied();
+                // Outside quotes a backslash escapes anything; inside double
+                // quotes (and here-documents) only the characters that would
+                // otherwise be special there.
+                let escapable = match next {
+                    None => false,
+                    Some(_) if mode == Mode::Word && !in_double => true,
+                    Some('"') => in_double,
+                    Some(c) => matches!(c, '$' | '`' | '\\' | '\n'),
+                };
+                if escapable {
+                    // A backslash-newline is a line continuation and vanishes.
+                    let escaped = next.unwrap();
+                    if escaped != '\n' {
+                        result.push(escaped, true);
+                    }
+                    result.has_quotes |= mode == Mode::Word;
+                    i += 2;
+                } else {
+                    result.push('\\', in_double);
+                    i += 1;
+                }
+            }
+            '$' if mode == Mode::Word && !in_double && chars.get(i + 1) == Some(&'\'') => {
+                let (decoded, end) = ansi_c_quoted(&chars, i + 2);
+                result.push_str(&decoded, true);
+                result.has_quotes = true;
+                i = end;
+            }
+            '$' | '`' => {
+                let mut expanded = String::new();
+                // Unquoted, the output of a command substitution and the
+                // parameters in `$@` and `$*` are split into fields.
+                let splittable = chars[i] == '`'
+                    || matches!(chars.get(i + 1), Some('(' | '@' | '*'))
+                    || matches!(chars.get(i + 1..i + 4), Some(['{', '@' | '*', '}']));
+                i = on_expansion(&chars, i, in_double, &mut expanded)?;
+                for (n, piece) in expanded.split(FIELD_BREAK).enumerate() {
+                    if n > 0 {
+                        result.push_break(in_double);
+                    }
+                    if splittable && mode == Mode::Word && !in_double {
+                        result.push_splittable(piece);
+                    } else {
+                        result.push_str(piece, in_double);
+                    }
+                }
+            }
+            c => {
+                result.push(c, in_double);
+                i += 1;
+            }
+        }
+    }
+    Ok(result)
+}
+
+// The directory a tilde prefix (the text after the `~`) stands for: `~` is
+// $HOME, `~+` is $PWD, `~-` is $OLDPWD and `~user` is that user's home
+// directory. None if the variable is unset or there's no such user.
+fn tilde_dir(prefix: &str, shell: &Shell) -> Option<String> {
+    match prefix {
+        "" => shell.vars.get("HOME").map(str::to_string),
+        "+" => shell.vars.get("PWD").map(str::to_string),
+        "-" => shell.vars.get("OLDPWD").map(str::to_string),
+        user => get_home_dir_by_name(user),
+    }
+}
+
+// Wraps `text` in single quotes so it comes through later expansion
+// untouched.
+fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+// Replaces an unquoted `~` prefix at the start of a raw word, and in an
+// assignment's value also after each unquoted `:` (as in
+// `PATH=~/bin:~/.local/bin`), with the directory it names. The prefix runs
+// up to the next `/` (or `:`), and isn't expanded if any of it is quoted
+// or it doesn't name anything. The directory is quoted in the result so
+// that wildcards in it aren't expanded.
+fn expand_tildes(word: &str, in_assignment: bool, shell: &Shell) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    let mask = unquoted_mask(&chars);
+    let ends_prefix = |j: usize| chars[j] == '/' || (in_assignment && chars[j] == ':');
+    let mut result = String::new();
+    let mut at_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        if at_start && chars[i] == '~' && mask[i] {
+            let end = (i + 1..chars.len())
+                .find(|&j| !mask[j] || ends_prefix(j))
+                .unwrap_or(chars.len());
+            let quoted = end < chars.len() && !mask[end];
+            let prefix: String = chars[i + 1..end].iter().collect();
+            if let Some(dir) = tilde_dir(&prefix, shell).filter(|_| !quoted) {
+                result.push_str(&single_quoted(&dir));
+                at_start = false;
+                i = end;
+                continue;
+            }
+        }
+        at_start = in_assignment && chars[i] == ':' && mask[i];
+        result.push(chars[i]);
+        i += 1;
+    }
+    result
+}
+
+fn expand_quoted(word: &str, mode: Mode, shell: &mut Shell) -> Result<Word, String> {
+    process_word(
+        word,
+        mode,
+        &mut |chars, i, in_double, text| match chars[i] {
+            '`' => expand_backticks(chars, i, in_double, text, shell),
+            _ => expand_parameter(chars, i, text, shell),
+        },
+    )
+}
+
+// Expands one word as written on the command line: a leading `~` is
+// replaced by a home directory, parameters (`$?`, `$NAME` and the `${...}`
+// forms) outside single quotes are replaced by their values, and the
+// quotes and escaping backslashes are removed. Errors for a malformed
+// `${...}` or a `${NAME:?message}` whose NAME is unset.
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<String, String> {
+    let word = expand_tildes(word, false, shell);
+    Ok(expand_quoted(&word, Mode::Word, shell)?.text)
+}
+
+// Expands the value of a `NAME=value` assignment, which is like any other
+// word except that a `~` after a `:` is expanded too.
+pub fn expand_assignment(value: &str, shell: &mut Shell) -> Result<String, String> {
+    let value = expand_tildes(value, true, shell);
+    Ok(expand_quoted(&value, Mode::Word, shell)?.text)
+}
+
+// Expands the text of an arithmetic expression before it's evaluated:
+// parameters and command substitutions are replaced and quotes removed,
+// but nothing is split or globbed, so `*` is still multiplication.
+pub fn expand_expression(expr: &str, shell: &mut Shell) -> Result<String, String> {
+    Ok(expand_quoted(expr, Mode::Word, shell)?.text)
+}
+
+// Expands a here-document body whose delimiter wasn't quoted.
+pub fn expand_heredoc(body: &str, shell: &mut Shell) -> Result<String, String> {
+    Ok(expand_quoted(body, Mode::HereDoc, shell)?.text)
+}
+
+// Removes quotes and escapes from `word` without expanding anything, as
+// for a here-document delimiter.
+pub fn remove_quotes(word: &str) -> String {
+    let mut keep = |chars: &[char], i: usize, _: bool, text: &mut String| {
+        text.push(chars[i]);
+        Ok(i + 1)
+    };
+    process_word(word, Mode::Word, &mut keep)
+        .map(|word| word.text)
+        .unwrap_or_default()
+}
+
+// Expands every word, ready for `glob::expand_all`: each comes back as a
+// glob pattern in which quoted wildcards are escaped. The output of an
+// unquoted command substitution, `$@` or `$*` is split into separate words
+// at the characters in `$IFS` (space, tab and newline by default), and
+// `"$@"` gives one word per positional parameter. A word that expands to
+// nothing at all and had no quoted part (like `$UNSET`, but not `""` or
+// `"$UNSET"`) is dropped rather than passed on as an empty argument, as in
+// POSIX shells.
+pub fn expand_words(words: &[String], shell: &mut Shell) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for word in words {
+        // With no positional parameters, `"$@"` is no word at all rather
+        // than an empty one.
+        if shell.positional.is_empty() && matches!(word.as_str(), "\"$@\"" | "\"${@}\"") {
+            continue;
+        }
+        let word = expand_tildes(word, false, shell);
+        let word = expand_quoted(&word, Mode::Word, shell)?;
+        let ifs = shell.vars.get("IFS").unwrap_or(" \t\n");
+        for field in word.fields(ifs) {
+            if !field.text.is_empty() || field.has_quotes {
+                expanded.push(field.pattern());
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell_with(vars: &[(&str, &str)]) -> Shell {
+        let mut shell = Shell::default();
+        for (name, value) in vars {
+            shell.vars.set(name, value).unwrap();
+        }
+        shell
+    }
+
+    fn expand(word: &str, shell: &mut Shell) -> String {
+        expand_word(word, shell).unwrap()
+    }
+
+    #[test]
+    fn expand_word_replaces_status() {
+        let mut shell = Shell {
+            last_status: 127,
+            ..Shell::default()
+        };
+        assert_eq!(expand("$?", &mut shell), "127");
+        assert_eq!(expand("status=$?.", &mut shell), "status=127.");
+        assert_eq!(expand("${?}", &mut shell), "127");
+    }
+
+    #[test]
+    fn expand_word_replaces_variables() {
+        let mut shell = shell_with(&[("NAME", "world"), ("DIR", "/tmp")]);
+        assert_eq!(expand("hello $NAME", &mut shell), "hello world");
+        assert_eq!(expand("$DIR/file", &mut shell), "/tmp/file");
+        assert_eq!(expand("${NAME}s", &mut shell), "worlds");
+        assert_eq!(expand("$NAMEs", &mut shell), "");
+        assert_eq!(expand("$UNSET", &mut shell), "");
+    }
+
+    #[test]
+    fn expand_word_leaves_lone_dollars_alone() {
+        let mut shell = Shell::default();
+        assert_eq!(expand("$ cost $. $", &mut shell), "$ cost $. $");
+    }
+
+    #[test]
+    fn expand_word_rejects_bad_substitutions() {
+        let mut shell = Shell::default();
+        assert!(expand_word("${unclosed", &mut shell).is_err());
+        assert!(expand_word("${1x}", &mut shell).is_err());
+        assert!(expand_word("${X!y}", &mut shell).is_err());
+    }
+
+    #[test]
+    fn default_and_alternative_values() {
+        let mut shell = shell_with(&[("SET", "yes"), ("EMPTY", "")]);
+        assert_eq!(expand("${SET:-no}", &mut shell), "yes");
+        assert_eq!(expand("${EMPTY:-no}", &mut shell), "no");
+        assert_eq!(expand("${EMPTY-no}", &mut shell), "");
+        assert_eq!(expand("${UNSET-$SET}", &mut shell), "yes");
+        assert_eq!(expand("${SET:+alt}", &mut shell), "alt");
+        assert_eq!(expand("${EMPTY:+alt}", &mut shell), "");
+        assert_eq!(expand("${EMPTY+alt}", &mut shell), "alt");
+        assert_eq!(expand(r#""${UNSET:-"q r"}""#, &mut shell), "q r");
+        assert_eq!(expand(r#""${SET:+"a }"}""#, &mut shell), "a }");
+    }
+
+    #[test]
+    fn assign_default_sets_the_variable() {
+        let mut shell = shell_with(&[("SET", "yes")]);
+        assert_eq!(expand("${NEW:=fresh}", &mut shell), "fresh");
+        assert_eq!(shell.vars.get("NEW"), Some("fresh"));
+        assert_eq!(expand("${SET:=no}", &mut shell), "yes");
+        assert_eq!(shell.vars.get("SET"), Some("yes"));
+    }
+
+    #[test]
+    fn error_if_unset_reports_message() {
+        let mut shell = shell_with(&[("SET", "yes")]);
+        assert_eq!(expand("${SET:?oops}", &mut shell), "yes");
+        assert_eq!(
+            expand_word("${UNSET:?need a value}", &mut shell).unwrap_err(),
+            "UNSET: need a value"
+        );
+        assert_eq!(
+            expand_word("${UNSET:?}", &mut shell).unwrap_err(),
+            "UNSET: parameter null or not set"
+        );
+    }
+
+    #[test]
+    fn length_counts_characters() {
+        let mut shell = shell_with(&[("WORD", "héllo")]);
+        assert_eq!(expand("${#WORD}", &mut shell), "5");
+        assert_eq!(expand("${#UNSET}", &mut shell), "0");
+    }
+
+    #[test]
+    fn pattern_removal_uses_glob_matching() {
+        let mut shell = shell_with(&[("FILE", "archive.tar.gz"), ("PATHNAME", "/a/b/c")]);
+        assert_eq!(expand("${FILE%.*}", &mut shell), "archive.tar");
+        assert_eq!(expand("${FILE%%.*}", &mut shell), "archive");
+        assert_eq!(expand("${PATHNAME#*/}", &mut shell), "a/b/c");
+        assert_eq!(expand("${PATHNAME##*/}", &mut shell), "c");
+        assert_eq!(expand("${FILE%.zip}", &mut shell), "archive.tar.gz");
+        assert_eq!(expand("${FILE#a?c}", &mut shell), "hive.tar.gz");
+    }
+
+    #[test]
+    fn expand_words_drops_words_that_expand_to_nothing() {
+        let mut shell = shell_with(&[("X", "1")]);
+        let words = vec![
+            "echo".to_string(),
+            "$UNSET".to_string(),
+            "\"\"".to_string(),
+            "\"$UNSET\"".to_string(),
+            "$X".to_string(),
+        ];
+        assert_eq!(
+            expand_words(&words, &mut shell).unwrap(),
+            vec!["echo", "", "", "1"]
+        );
+    }
+
+    #[test]
+    fn expand_words_escapes_quoted_wildcards() {
+        let mut shell = shell_with(&[("STAR", "*")]);
+        let words: Vec<String> = [
+            r"*.txt",
+            r#""*.txt""#,
+            r"\*",
+            r"'a?'*",
+            "$STAR",
+            r#""$STAR""#,
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        assert_eq!(
+            expand_words(&words, &mut shell).unwrap(),
+            vec![r"*.txt", r"\*.txt", r"\*", r"a\?*", "*", r"\*"]
+        );
+    }
+
+    #[test]
+    fn pattern_removal_respects_quoting() {
+        let mut shell = shell_with(&[("X", "a*b*c")]);
+        assert_eq!(expand(r#"${X%"*"c}"#, &mut shell), "a*b");
+        assert_eq!(expand("${X%*c}", &mut shell), "a*b*");
+    }
+
+    #[test]
+    fn expand_word_removes_quotes() {
+        let mut shell = shell_with(&[("X", "val")]);
+        assert_eq!(expand(r#""a  b"'c'd"#, &mut shell), "a  bcd");
+        assert_eq!(expand(r#""$X" '$X' "'$X'""#, &mut shell), "val $X 'val'");
+        assert_eq!(expand(r#""a;b|c""#, &mut shell), "a;b|c");
+    }
+
+    #[test]
+    fn expand_word_handles_backslash_escapes() {
+        let mut shell = shell_with(&[("X", "val")]);
+        assert_eq!(expand(r"\$X a\ b \\ \'", &mut shell), r"$X a b \ '");
+        assert_eq!(expand(r#""\$X \"q\" \\ \n""#, &mut shell), r#"$X "q" \ \n"#);
+        assert_eq!(expand(r"'\n'", &mut shell), r"\n");
+        assert_eq!(expand("a\\\nb", &mut shell), "ab");
+    }
+
+    #[test]
+    fn expand_word_decodes_ansi_c_quotes() {
+        let mut shell = Shell::default();
+        assert_eq!(expand(r"$'a\tb\n'", &mut shell), "a\tb\n");
+        assert_eq!(expand(r"$'it\'s'", &mut shell), "it's");
+        assert_eq!(expand(r"$'\x41\101é\e'", &mut shell), "AA\u{e9}\x1b");
+        assert_eq!(expand(r"$'\q $X'", &mut shell), r"\q $X");
+    }
+
+    #[test]
+    fn expand_heredoc_keeps_quotes_but_expands() {
+        let mut shell = shell_with(&[("X", "val")]);
+        assert_eq!(
+            expand_heredoc("'$X' \"$X\" \\$X \\q\n", &mut shell).unwrap(),
+            "'val' \"val\" $X \\q\n"
+        );
+    }
+
+    #[test]
+    fn remove_quotes_leaves_dollars_alone() {
+        assert_eq!(remove_quotes(r#"'E'"O"F"#), "EOF");
+        assert_eq!(remove_quotes(r"\$END"), "$END");
+        assert_eq!(remove_quotes("$END"), "$END");
+    }
+
+    #[test]
+    fn expand_word_expands_leading_tildes() {
+        let mut shell = shell_with(&[("HOME", "/home/me"), ("PWD", "/here"), ("OLDPWD", "/there")]);
+        assert_eq!(expand("~", &mut shell), "/home/me");
+        assert_eq!(expand("~/src", &mut shell), "/home/me/src");
+        assert_eq!(expand("~+/x", &mut shell), "/here/x");
+        assert_eq!(expand("~-", &mut shell), "/there");
+        assert_eq!(expand("a~", &mut shell), "a~");
+        assert_eq!(expand("x/~", &mut shell), "x/~");
+    }
+
+    #[test]
+    fn expand_word_leaves_quoted_and_unknown_tildes() {
+        let mut shell = shell_with(&[("HOME", "/home/me")]);
+        for word in [r"\~", "'~'", "\"~\"/x", "~'me'/x", "~no_such_user_here"] {
+            assert_eq!(expand(word, &mut shell), remove_quotes(word));
+        }
+        assert_eq!(expand("~-", &mut shell), "~-");
+    }
+
+    #[test]
+    fn expand_word_looks_up_other_users() {
+        let mut shell = Shell::default();
+        let home = get_home_dir_by_name("root").unwrap();
+        assert_eq!(expand("~root/x", &mut shell), format!("{home}/x"));
+    }
+
+    #[test]
+    fn expanded_tildes_are_not_globbed() {
+        let mut shell = shell_with(&[("HOME", "/it's [a]*")]);
+        assert_eq!(
+            expand_words(&["~/*".to_string()], &mut shell).unwrap(),
+            vec![r"/it's \[a\]\*/*"]
+        );
+    }
+
+    #[test]
+    fn expand_assignment_expands_after_colons() {
+        let mut shell = shell_with(&[("HOME", "/h")]);
+        assert_eq!(
+            expand_assignment("~/bin:~/.local/bin:/usr/bin", &mut shell).unwrap(),
+            "/h/bin:/h/.local/bin:/usr/bin"
+        );
+        assert_eq!(
+            expand_assignment("\"a:~\":~", &mut shell).unwrap(),
+            "a:~:/h"
+        );
+        assert_eq!(expand("a:~", &mut shell), "a:~");
+    }
+
+    #[test]
+    fn expand_word_substitutes_command_output() {
+        let mut shell = shell_with(&[("X", "val")]);
+        assert_eq!(expand("$(echo hi)", &mut shell), "hi");
+        assert_eq!(expand("\"at $(echo $X)!\"", &mut shell), "at val!");
+        assert_eq!(expand("$(echo $(echo nested))", &mut shell), "nested");
+        assert_eq!(expand(r"`echo \`echo old\``", &mut shell), "old");
+        assert_eq!(expand("'$(echo no)'", &mut shell), "$(echo no)");
+        assert_eq!(expand(r"\$(echo no)", &mut shell), "$(echo no)");
+    }
+
+    #[test]
+    fn command_substitution_strips_trailing_newlines_only() {
+        let mut shell = Shell::default();
+        assert_eq!(expand("\"$(echo; echo a; echo; echo)\"", &mut shell), "\na");
+    }
+
+    #[test]
+    fn command_substitution_runs_in_a_subshell() {
+        let mut shell = shell_with(&[("X", "outer")]);
+        assert_eq!(expand("$(X=inner; echo $X)", &mut shell), "inner");
+        assert_eq!(shell.vars.get("X"), Some("outer"));
+        assert_eq!(expand("$(exit 4)", &mut shell), "");
+        assert_eq!(shell.last_status, 4);
+        assert_eq!(shell.exit_status, None);
+    }
+
+    #[test]
+    fn expand_words_splits_unquoted_substitutions() {
+        let mut shell = shell_with(&[("V", "a  b")]);
+        let words = |input: &[&str], shell: &mut Shell| {
+            let words: Vec<String> = input.iter().map(ToString::to_string).collect();
+            expand_words(&words, shell).unwrap()
+        };
+        assert_eq!(words(&["$(echo ' a  b ')"], &mut shell), vec!["a", "b"]);
+        assert_eq!(words(&["x$(echo 'a b')y"], &mut shell), vec!["xa", "by"]);
+        assert_eq!(words(&["\"$(echo 'a b')\""], &mut shell), vec!["a b"]);
+        assert_eq!(words(&["$V"], &mut shell), vec!["a  b"]);
+        assert!(words(&["$(echo)"], &mut shell).is_empty());
+    }
+
+    #[test]
+    fn expand_words_splits_on_ifs() {
+        let mut shell = shell_with(&[("IFS", ":")]);
+        let words: Vec<String> = vec!["$(echo ':a::b:')".to_string()];
+        assert_eq!(
+            expand_words(&words, &mut shell).unwrap(),
+            vec!["", "a", "", "b"]
+        );
+        shell.vars.set("IFS", "").unwrap();
+        let words: Vec<String> = vec!["$(echo 'a b')".to_string()];
+        assert_eq!(expand_words(&words, &mut shell).unwrap(), vec!["a b"]);
+    }
+
+    fn shell_with_args(name: &str, args: &[&str]) -> Shell {
+        Shell {
+            name: name.to_string(),
+            positional: args.iter().map(ToString::to_string).collect(),
+            ..Shell::default()
+        }
+    }
+
+    #[test]
+    fn expand_word_replaces_positional_parameters() {
+        let args = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let mut shell = shell_with_args("script.sh", &args);
+        assert_eq!(expand("$0 $1 $9 $10", &mut shell), "script.sh a i a0");
+        assert_eq!(expand("${10} ${11:-none}", &mut shell), "j none");
+        assert_eq!(expand("$# ${#} ${#@} ${#0}", &mut shell), "10 10 10 9");
+        let mut shell = shell_with_args("sh", &["one", "two"]);
+        assert_eq!(expand("[$*] [$@]", &mut shell), "[one two] [one two]");
+        assert_eq!(expand("${1}x ${3-unset}", &mut shell), "onex unset");
+        assert!(expand_word("${3=x}", &mut shell).is_err());
+    }
+
+    #[test]
+    fn quoted_at_sign_keeps_parameters_apart() {
+        let mut shell = shell_with_args("sh", &["a b", "", "c"]);
+        let words =
+            |list: &[&str]| -> Vec<String> { list.iter().map(ToString::to_string).collect() };
+        assert_eq!(
+            expand_words(&words(&["\"$@\""]), &mut shell).unwrap(),
+            vec!["a b", "", "c"]
+        );
+        assert_eq!(
+            expand_words(&words(&["x\"$@\"y"]), &mut shell).unwrap(),
+            vec!["xa b", "", "cy"]
+        );
+        assert_eq!(
+            expand_words(&words(&["$@"]), &mut shell).unwrap(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            expand_words(&words(&["\"$*\""]), &mut shell).unwrap(),
+            vec!["a b  c"]
+        );
+        let mut shell = shell_with_args("sh", &[]);
+        assert_eq!(
+            expand_words(&words(&["\"$@\"", "$*", "\"${@}\""]), &mut shell).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn expand_word_evaluates_arithmetic() {
+        let mut shell = shell_with(&[("N", "4")]);
+        assert_eq!(expand("$((1 + 2 * 3))", &mut shell), "7");
+        assert_eq!(expand("$(( N * $N ))", &mut shell), "16");
+        assert_eq!(expand("\"$((N++)) $((N))\"", &mut shell), "4 5");
+        assert_eq!(expand("$(( $(echo 2) ** 10 ))", &mut shell), "1024");
+        assert_eq!(expand("$(( (1 + 2) * 3 ))", &mut shell), "9");
+        assert_eq!(
+            expand_word("$((1 / 0))", &mut shell).unwrap_err(),
+            "1 / 0: division by 0"
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use crate::interrupt;
+use crate::redirect::FdTable;
+use crate::vars::Variables;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+
+// Why a command couldn't be run, mirroring the distinctions bash makes:
+// nothing by that name (127) vs. something that exists but can't be
+// executed (126).
+#[derive(Debug, PartialEq)]
+pub enum LookupError {
+    NotFound,
+    NoSuchFile,
+    PermissionDenied,
+    IsADirectory,
+}
+
+impl LookupError {
+    pub fn status(&self) -> i32 {
+        match self {
+            LookupError::NotFound | LookupError::NoSuchFile => 127,
+            LookupError::PermissionDenied | LookupError::IsADirectory => 126,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            LookupError::NotFound => "command not found",
+            LookupError::NoSuchFile => "No such file or directory",
+            LookupError::PermissionDenied => "Permission denied",
+            LookupError::IsADirectory => "Is a directory",
+        }
+    }
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+// Resolves `command` to a program path. A name containing `/` is used as-is
+// (relative to the current directory); anything else is searched for in each
+// entry of `path_var` (the shell's `$PATH`) in order, with an empty entry
+// meaning the current directory. Like bash, a non-executable match is only
+// reported as "Permission denied" if no executable one turns up later in
+// `$PATH`.
+pub fn find_program(command: &OsStr, path_var: &str) -> Result<PathBuf, LookupError> {
+    if command.as_bytes().contains(&b'/') {
+        let path = Path::new(command);
+        return match fs::metadata(path) {
+            Err(_) => Err(LookupError::NoSuchFile),
+            Ok(m) if m.is_dir() => Err(LookupError::IsADirectory),
+            Ok(_) if !is_executable(path) => Err(LookupError::PermissionDenied),
+            Ok(_) => Ok(path.to_path_buf()),
+        };
+    }
+
+    let mut found_non_executable = false;
+    for dir in path_var.split(':') {
+        let dir = if dir.is_empty() { "." } else { dir };
+        let candidate = Path::new(dir).join(command);
+        if is_executable(&candidate) {
+            return Ok(candidate);
+        }
+        if candidate.is_file() {
+            found_non_executable = true;
+        }
+    }
+
+    if found_non_executable {
+        Err(LookupError::PermissionDenied)
+    } else {
+        Err(LookupError::NotFound)
+    }
+}
+
+// The signals the terminal sends for Ctrl-C, Ctrl-\ and Ctrl-Z. They go
+// to the shell as well as to the command running in the foreground, so an
+// interactive shell mustn't let them end or stop it.
+const TERMINAL_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP];
+
+// Makes an interactive shell survive the terminal's signals, so that Ctrl-C
+// stops the command it's waiting for rather than the shell itself. SIGQUIT
+// and SIGTSTP are ignored, but SIGINT is caught, since built-ins run inside
+// the shell and Ctrl-C has to be able to stop them too (see `interrupt`).
+pub fn handle_terminal_signals() {
+    unsafe {
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+    }
+    interrupt::catch();
+}
+
+// Converts a child's exit status into the number a shell reports for it:
+// the exit code, or 128 + the signal number if it was killed by a signal.
+pub fn status_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+// Starts an external program with `args`, running it with the descriptors in
+// `fds` and the exported variables of `vars` as its environment. On failure
+// the error has already been reported (to wherever `fds` points stderr, as
+// if the child had printed it) and the shell exit status to use is returned
+// instead.
+pub fn spawn_external(
+    command: &OsStr,
+    args: &[OsString],
+    fds: &FdTable,
+    vars: &Variables,
+) -> Result<Child, i32> {
+    let program = match find_program(command, vars.get("PATH").unwrap_or_default()) {
+        Ok(program) => program,
+        Err(e) => {
+            let command = command.to_string_lossy();
+            fds.report(&format!("{command}: {}", e.message()));
+            return Err(e.status());
+        }
+    };
+
+    let mut child = Command::new(&program);
+    child
+        .arg0(command)
+        .args(args)
+        .env_clear()
+        .envs(vars.exported());
+    // An ignored signal stays ignored across exec, so put back the default
+    // actions the shell may have turned off.
+    unsafe {
+        child.pre_exec(|| {
+            for signal in TERMINAL_SIGNALS {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+            Ok(())
+        });
+    }
+    let spawned = fds.configure(&mut child).and_then(|()| child.spawn());
+    spawned.map_err(|e| {
+        fds.report(&format!("{}: {e}", command.to_string_lossy()));
+        if e.kind() == io::ErrorKind::NotFound {
+            127
+        } else {
+            126
+        }
+    })
+}
+
+// Waits for a child started by `spawn_external` and returns its shell exit
+// status. There's no job control to hand a stopped child (Ctrl-Z) over to,
+// so one that stops is just set going again rather than left for the shell
+// to wait on forever.
+pub fn wait_external(command: &str, child: Child) -> i32 {
+    let pid = child.id() as libc::pid_t;
+    loop {
+        let mut raw = 0;
+        if unsafe { libc::waitpid(pid, &mut raw, libc::WUNTRACED) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            eprintln!("{command}: {e}");
+            return 1;
+        }
+        if libc::WIFSTOPPED(raw) {
+            unsafe { libc::kill(pid, libc::SIGCONT) };
+            continue;
+        }
+        let status = ExitStatus::from_raw(raw);
+        report_signal(status);
+        return status_code(status);
+    }
+}
+
+// Like bash, mention a child that died from a signal, except for the
+// ordinary Ctrl-C and broken-pipe cases.
+fn report_signal(status: ExitStatus) {
+    let Some(signal) = status.signal() else {
+        return;
+    };
+    if signal == libc::SIGINT || signal == libc::SIGPIPE {
+        return;
+    }
+    let description = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(signal)) };
+    let core = if status.core_dumped() {
+        " (core dumped)"
+    } else {
+        ""
+    };
+    eprintln!("{}{core}", description.to_string_lossy());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "zero_shell_external_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn find_program_searches_path() {
+        let path_var = env::var("PATH").unwrap();
+        assert!(find_program(OsStr::new("sh"), &path_var)
+            .unwrap()
+            .ends_with("sh"));
+    }
+
+    #[test]
+    fn find_program_reports_missing_command() {
+        assert_eq!(
+            find_program(OsStr::new("zero_shell_no_such_command"), "/nonexistent"),
+            Err(LookupError::NotFound)
+        );
+    }
+
+    #[test]
+    fn find_program_uses_paths_with_a_slash_directly() {
+        let dir = temp_dir("slash");
+        let script = dir.join("run.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        let path = script.as_os_str();
+
+        assert_eq!(find_program(path, ""), Err(LookupError::PermissionDenied));
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_program(path, ""), Ok(script.clone()));
+
+        let missing = dir.join("missing");
+        assert_eq!(
+            find_program(missing.as_os_str(), ""),
+            Err(LookupError::NoSuchFile)
+        );
+        assert_eq!(
+            find_program(dir.as_os_str(), ""),
+            Err(LookupError::IsADirectory)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lookup_errors_use_bash_exit_statuses() {
+        assert_eq!(LookupError::NotFound.status(), 127);
+        assert_eq!(LookupError::NoSuchFile.status(), 127);
+        assert_eq!(LookupError::PermissionDenied.status(), 126);
+        assert_eq!(LookupError::IsADirectory.status(), 126);
+    }
+
+    fn args(strs: &[&str]) -> Vec<OsString> {
+        strs.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn spawn_external_runs_program_and_reports_status() {
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let mut fds = FdTable::inherited();
+        fds.set(1, writer);
+        let vars = Variables::from_env();
+        let child = spawn_external(
+            OsStr::new("sh"),
+            &args(&["-c", "echo hi; exit 3"]),
+            &fds,
+            &vars,
+        )
+        .unwrap();
+        drop(fds);
+
+        let mut stdout = String::new();
+        reader.read_to_string(&mut stdout).unwrap();
+        assert_eq!(stdout, "hi\n");
+        assert_eq!(wait_external("sh", child), 3);
+    }
+
+    #[test]
+    fn spawn_external_reports_missing_command_status() {
+        let mut fds = FdTable::inherited();
+        fds.set(2, std::fs::File::open("/dev/null").unwrap());
+        let result = spawn_external(
+            OsStr::new("zero_shell_no_such_command"),
+            &[],
+            &fds,
+            &Variables::from_env(),
+        );
+        assert_eq!(result.err(), Some(127));
+    }
+
+    #[test]
+    fn wait_external_reports_signals_as_128_plus_signal() {
+        let child = spawn_external(
+            OsStr::new("sh"),
+            &args(&["-c", "kill -TERM $$"]),
+            &FdTable::inherited(),
+            &Variables::from_env(),
+        )
+        .unwrap();
+        assert_eq!(wait_external("sh", child), 128 + libc::SIGTERM);
+    }
+
+    // Runs the ignored test `name` by itself in a new copy of the test
+    // binary, for checks that change how the whole process handles
+    // signals and so would disturb the tests running alongside them.
+    fn run_alone(name: &str) {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", name, "--ignored", "--test-threads=1"])
+            .output()
+            .unwrap();
+        let report = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success() && report.contains("1 passed"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn children_get_the_terminal_signals_back() {
+        run_alone("external::tests::terminal_signals_are_reset_for_children");
+    }
+
+    #[test]
+    #[ignore = "changes signal handling for the whole process; run by children_get_the_terminal_signals_back"]
+    fn terminal_signals_are_reset_for_children() {
+        handle_terminal_signals();
+        for (signal, name) in [(libc::SIGINT, "INT"), (libc::SIGQUIT, "QUIT")] {
+            let child = spawn_external(
+                OsStr::new("sh"),
+                &args(&["-c", &format!("kill -{name} $$; exit 9")]),
+                &FdTable::inherited(),
+                &Variables::from_env(),
+            );
+            assert_eq!(wait_external("sh", child.unwrap()), 128 + signal);
+        }
+    }
+
+    #[test]
+    fn wait_external_sets_stopped_children_going_again() {
+        let child = spawn_external(
+            OsStr::new("sh"),
+            &args(&["-c", "kill -STOP $$; exit 4"]),
+            &FdTable::inherited(),
+            &Variables::from_env(),
+        )
+        .unwrap();
+        assert_eq!(wait_external("sh", child), 4);
+    }
+
+    #[test]
+    fn spawn_external_passes_only_exported_variables() {
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let mut fds = FdTable::inherited();
+        fds.set(1, writer);
+        let mut vars = Variables::from_env();
+        vars.set("ZERO_SHELL_SHARED", "yes").unwrap();
+        vars.export("ZERO_SHELL_SHARED");
+        vars.set("ZERO_SHELL_LOCAL", "no").unwrap();
+        let script = "echo \"$ZERO_SHELL_SHARED-$ZERO_SHELL_LOCAL\"";
+        let child = spawn_external(OsStr::new("sh"), &args(&["-c", script]), &fds, &vars).unwrap();
+        drop(fds);
+
+        let mut stdout = String::new();
+        reader.read_to_string(&mut stdout).unwrap();
+        assert_eq!(stdout, "yes-\n");
+        assert_eq!(wait_external("sh", child), 0);
+    }
+}
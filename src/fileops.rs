@@ -1,4 +1,5 @@
-use std::fs;
+use crate::interrupt;
+use std::fs::{self, File};
 use std::path::Path;
 
 pub fn remove_item(path: &Path, recursive: bool) -> Result<(), String> {
@@ -32,9 +33,15 @@ pub fn copy_file(source: &Path, destination: &Path) -> Result<(), String> {
         destination.to_path_buf()
     };
 
-    fs::copy(source, destination)
-        .map(|_| ())
-        .map_err(|e| e.to_string())
+    // Copied a chunk at a time, rather than with `fs::copy`, so that Ctrl-C
+    // can stop a big copy part way.
+    let copied = File::open(source).and_then(|mut reader| {
+        let permissions = reader.metadata()?.permissions();
+        let mut writer = File::create(&destination)?;
+        interrupt::copy(&mut reader, &mut writer)?;
+        writer.set_permissions(permissions)
+    });
+    copied.map_err(|e| e.to_string())
 }
 
 pub fn move_item(source: &Path, destination: &Path) -> Result<(), String> {
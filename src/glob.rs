@@ -1,75 +1,418 @@
+use std::ffi::{OsStr, OsString};
 use std::fs;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
-// `*` matches any run of characters (including none); `?` matches exactly
-// one character. No character classes or brace expansion — this only needs
-// to cover the common *.ext / file?.txt cases.
-fn wildcard_matches(pattern: &str, name: &str) -> bool {
-    let pattern: Vec<char> = pattern.chars().collect();
-    let name: Vec<char> = name.chars().collect();
-    wildcard_matches_chars(&pattern, &name)
+// Whether `c` belongs to the POSIX character class `name` (as in
+// `[[:digit:]]`). An unknown class matches nothing.
+fn class_matches(name: &str, c: char) -> bool {
+    match name {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+// Matches `c` against the bracket expression at the start of `pattern`
+// (which begins with `[`): a set of characters, `a-z` ranges and
+// `[:class:]`es, negated by a leading `!` or `^`. A `]` straight after the
+// opening bracket (or its `!`) is a member rather than the end, and a
+// backslash escapes the character after it. Returns whether `c` matched and
+// how many pattern characters the expression took up, or None if there's
+// no closing `]`, in which case the `[` is just an ordinary character.
+// With `fold_case`, `c` also matches in its other case.
+fn match_bracket(pattern: &[char], c: char, fold_case: bool) -> Option<(bool, usize)> {
+    let cases = if fold_case {
+        vec![c, c.to_ascii_lowercase(), c.to_ascii_uppercase()]
+    } else {
+        vec![c]
+    };
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let literal_at = |i: usize| -> Option<(char, usize)> {
+        match *pattern.get(i)? {
+            '\\' => pattern.get(i + 1).map(|&c| (c, i + 2)),
+            c => Some((c, i + 1)),
+        }
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match *pattern.get(i)? {
+            ']' if !first => return Some((matched != negated, i + 1)),
+            '[' if pattern.get(i + 1) == Some(&':') => {
+                let rest: String = pattern[i + 2..].iter().collect();
+                let end = rest.find(":]")?;
+                matched |= cases.iter().any(|&c| class_matches(&rest[..end], c));
+                i += 2 + rest[..end].chars().count() + 2;
+            }
+            _ => {
+                let (low, next) = literal_at(i)?;
+                i = next;
+                let is_range = pattern.get(i) == Some(&'-') && pattern.get(i + 1) != Some(&']');
+                match literal_at(i + 1).filter(|_| is_range) {
+                    Some((high, next)) => {
+                        matched |= cases.iter().any(|&c| low <= c && c <= high);
+                        i = next;
+                    }
+                    None => matched |= cases.contains(&low),
+                }
+            }
+        }
+        first = false;
+    }
 }
 
-fn wildcard_matches_chars(pattern: &[char], name: &[char]) -> bool {
-    match pattern.first() {
-        None => name.is_empty(),
-        Some('*') => (0..=name.len()).any(|i| wildcard_matches_chars(&pattern[1..], &name[i..])),
-        Some('?') => !name.is_empty() && wildcard_matches_chars(&pattern[1..], &name[1..]),
-        Some(&c) => name.first() == Some(&c) && wildcard_matches_chars(&pattern[1..], &name[1..]),
+// One element of a compiled pattern.
+enum Token {
+    // A character that matches only itself (or its other case, when
+    // ignoring case).
+    Literal(char),
+    // `?`: any one character.
+    Any,
+    // `*`: any run of characters, including none.
+    Star,
+    // A bracket expression's text, from the `[` to the closing `]`.
+    Bracket(Vec<char>),
+}
+
+// Splits a pattern into tokens. An unclosed `[` and an escaped character
+// become literals, and a run of `*`s collapses into one.
+fn compile(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (token, len) = match chars[i] {
+            '*' => (Token::Star, 1),
+            '?' => (Token::Any, 1),
+            '[' => match match_bracket(&chars[i..], ' ', false) {
+                Some((_, len)) => (Token::Bracket(chars[i..i + len].to_vec()), len),
+                None => (Token::Literal('['), 1),
+            },
+            '\\' if i + 1 < chars.len() => (Token::Literal(chars[i + 1]), 2),
+            c => (Token::Literal(c), 1),
+        };
+        if !matches!((&token, tokens.last()), (Token::Star, Some(Token::Star))) {
+            tokens.push(token);
+        }
+        i += len;
     }
+    tokens
+}
+
+// The character at the start of `name` and how many bytes it takes up.
+// A byte that doesn't start valid UTF-8 comes back as None on its own, so
+// names that aren't UTF-8 can still be matched (by `?`, `*` and negated
+// bracket expressions).
+fn next_char(name: &[u8]) -> (Option<char>, usize) {
+    let len = match name[0] {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        0xC0.. => 2,
+        _ => 1,
+    };
+    match name.get(..len).map(std::str::from_utf8) {
+        Some(Ok(s)) => (s.chars().next(), len),
+        _ => (None, 1),
+    }
+}
+
+// Whether a single-character token matches `c`.
+fn token_matches(token: &Token, c: Option<char>, fold_case: bool) -> bool {
+    match (token, c) {
+        (Token::Any, _) => true,
+        (Token::Literal(p), Some(c)) if fold_case => p.eq_ignore_ascii_case(&c),
+        (Token::Literal(p), Some(c)) => *p == c,
+        (Token::Bracket(set), Some(c)) => match_bracket(set, c, fold_case).is_some_and(|(m, _)| m),
+        (Token::Bracket(set), None) => matches!(set.get(1), Some('!' | '^')),
+        _ => false,
+    }
+}
+
+// Matches a compiled pattern against the raw bytes of a name. Rather than
+// trying every split at every `*` (exponential for patterns like
+// `*a*a*a*b`), only the most recent `*` is ever backtracked to: whatever an
+// earlier `*` could have absorbed, the later one can absorb instead. That
+// bounds the work at the pattern's length times the name's.
+fn tokens_match(tokens: &[Token], name: &[u8], fold_case: bool) -> bool {
+    let (mut t, mut n) = (0, 0);
+    // The token after the last `*` seen, and how far into the name that
+    // `*` currently reaches.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if let Some(Token::Star) = tokens.get(t) {
+            t += 1;
+            star = Some((t, n));
+            continue;
+        }
+        let (c, len) = next_char(&name[n..]);
+        if tokens
+            .get(t)
+            .is_some_and(|token| token_matches(token, c, fold_case))
+        {
+            t += 1;
+            n += len;
+            continue;
+        }
+        let Some((after_star, reach)) = star else {
+            return false;
+        };
+        let reach = reach + next_char(&name[reach..]).1;
+        star = Some((after_star, reach));
+        t = after_star;
+        n = reach;
+    }
+    tokens[t..].iter().all(|token| matches!(token, Token::Star))
+}
+
+// `*` matches any run of characters (including none); `?` matches exactly
+// one character; `[...]` matches one character from a set (see
+// `match_bracket`); and a backslash makes the character after it match
+// only itself (which is how quoted wildcards reach us).
+pub fn wildcard_matches(pattern: &str, name: &str) -> bool {
+    tokens_match(&compile(pattern), name.as_bytes(), false)
 }
 
+// Whether `token` has any unescaped wildcards (counting a `[` only if it
+// starts a complete bracket expression).
 fn is_pattern(token: &str) -> bool {
-    token.contains('*') || token.contains('?')
+    let chars: Vec<char> = token.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if match_bracket(&chars[i..], ' ', false).is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
 }
 
-// Expands a single glob token (e.g. "*.txt" or "sub/*.rs") into the sorted
-// list of matching paths, preserving the token's directory prefix. Returns
-// None if the token isn't a glob or matches nothing, so the caller can fall
-// back to the literal token — matching normal shell behavior when nothing
-// matches (the pattern is left as-is rather than expanding to nothing).
-fn expand(token: &str) -> Option<Vec<String>> {
-    if !is_pattern(token) {
-        return None;
+// Turns a pattern back into the literal text it was made from, for a word
+// that isn't used as a glob after all.
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+// Settings that change how patterns are expanded, switched with `shopt`
+// or `set -o`.
+#[derive(Clone, Debug, Default)]
+pub struct GlobOptions {
+    // `*`, `?` and `[...]` match a leading `.` too.
+    pub dotglob: bool,
+    // A pattern that matches nothing is an error.
+    pub failglob: bool,
+    // `**` matches any number of directories, including none.
+    pub globstar: bool,
+    // Letters match regardless of case.
+    pub nocaseglob: bool,
+    // A pattern that matches nothing expands to nothing.
+    pub nullglob: bool,
+}
+
+impl GlobOptions {
+    // Every option's `shopt` name and current setting.
+    pub fn flags(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("dotglob", self.dotglob),
+            ("failglob", self.failglob),
+            ("globstar", self.globstar),
+            ("nocaseglob", self.nocaseglob),
+            ("nullglob", self.nullglob),
+        ]
     }
 
-    let path = Path::new(token);
-    let file_pattern = path.file_name()?.to_string_lossy().to_string();
-    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
-    let dir = parent.unwrap_or_else(|| Path::new("."));
+    pub fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "nocaseglob" => Some(&mut self.nocaseglob),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
 
-    let mut matches: Vec<String> = fs::read_dir(dir)
-        .ok()?
+// Joins a matched name onto the path matched so far, as it should appear
+// in the result. Paths are kept as `OsString`s throughout, so a name that
+// isn't UTF-8 can still be searched and is passed on exactly.
+fn join(prefix: &OsStr, name: &OsStr) -> OsString {
+    let mut path = prefix.to_os_string();
+    if !prefix.is_empty() && !prefix.as_bytes().ends_with(b"/") {
+        path.push("/");
+    }
+    path.push(name);
+    path
+}
+
+// The directory to read for the path matched so far.
+fn dir_path(prefix: &OsStr) -> &Path {
+    Path::new(if prefix.is_empty() {
+        OsStr::new(".")
+    } else {
+        prefix
+    })
+}
+
+// The names in directory `prefix` (the current directory if it's empty)
+// that `pattern` matches. Unless dotglob is on, dotfiles only match a
+// pattern that starts with `.` itself.
+fn matching_entries(prefix: &OsStr, pattern: &str, options: &GlobOptions) -> Vec<OsString> {
+    let Ok(entries) = fs::read_dir(dir_path(prefix)) else {
+        return Vec::new();
+    };
+    let tokens = compile(pattern);
+    entries
         .filter_map(|entry| entry.ok())
-        .filter_map(|entry| {
-            let name = entry.file_name().to_string_lossy().to_string();
-            if name.starts_with('.') && !file_pattern.starts_with('.') {
-                return None;
+        .map(|entry| entry.file_name())
+        .filter(|name| {
+            !name.as_bytes().starts_with(b".") || pattern.starts_with('.') || options.dotglob
+        })
+        .filter(|name| tokens_match(&tokens, name.as_bytes(), options.nocaseglob))
+        .map(|name| join(prefix, &name))
+        .collect()
+}
+
+// Everything below `prefix` for `**`: with `dirs_only`, `prefix` itself and
+// every directory beneath it; otherwise every file and directory beneath
+// it. Hidden entries are skipped unless `dotglob` is set, and symlinks to
+// directories aren't followed, so a link loop can't recurse forever.
+fn globstar_entries(prefix: &OsStr, dirs_only: bool, dotglob: bool) -> Vec<OsString> {
+    let mut found = Vec::new();
+    if dirs_only {
+        found.push(prefix.to_os_string());
+    }
+    let mut pending = vec![prefix.to_os_string()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(dir_path(&dir)) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name();
+            if name.as_bytes().starts_with(b".") && !dotglob {
+                continue;
             }
-            if !wildcard_matches(&file_pattern, &name) {
-                return None;
+            let path = join(&dir, &name);
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            if is_dir {
+                pending.push(path.clone());
             }
-            Some(match parent {
-                Some(p) => p.join(&name).to_string_lossy().to_string(),
-                None => name,
-            })
-        })
-        .collect();
+            if is_dir || !dirs_only {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+// Expands a single glob token (e.g. "*.txt", "src/*/mod.rs" or, with
+// globstar, "src/**/*.rs") into the sorted list of matching paths. Each
+// `/`-separated component is matched in turn against the directories the
+// previous ones matched; components without wildcards are taken as they
+// are. A trailing `/` only matches directories. Returns None if the token
+// isn't a glob or matches nothing, leaving it to the caller to decide what
+// an unmatched pattern turns into. Names that aren't valid UTF-8 are
+// matched byte for byte and come back unchanged.
+fn expand(token: &str, options: &GlobOptions) -> Option<Vec<OsString>> {
+    if !is_pattern(token) {
+        return None;
+    }
+
+    let mut components: Vec<&str> = token.split('/').collect();
+    let trailing_slash = components.len() > 1 && components.last() == Some(&"");
+    if trailing_slash {
+        components.pop();
+    }
+    let mut matches = vec![OsString::new()];
+    if token.starts_with('/') {
+        matches = vec![OsString::from("/")];
+        components.remove(0);
+    }
+
+    let last = components.len().saturating_sub(1);
+    for (i, component) in components.iter().enumerate() {
+        let dirs_only = i < last || trailing_slash;
+        matches = if component.is_empty() {
+            // A doubled `//` just stays in the same directory.
+            matches
+        } else if *component == "**" && options.globstar {
+            matches
+                .iter()
+                .flat_map(|prefix| globstar_entries(prefix, dirs_only, options.dotglob))
+                .collect()
+        } else if !is_pattern(component) {
+            let name = OsString::from(unescape(component));
+            matches.iter().map(|prefix| join(prefix, &name)).collect()
+        } else {
+            matches
+                .iter()
+                .flat_map(|prefix| matching_entries(prefix, component, options))
+                .collect()
+        };
+        if dirs_only {
+            matches.retain(|path| path.is_empty() || Path::new(path).is_dir());
+        }
+    }
 
+    // Literal components were never checked against the filesystem.
+    matches.retain(|path| !path.is_empty() && fs::symlink_metadata(path).is_ok());
     if matches.is_empty() {
         return None;
     }
+    if trailing_slash {
+        for path in &mut matches {
+            path.push("/");
+        }
+    }
     matches.sort();
+    matches.dedup();
     Some(matches)
 }
 
-// Expands every glob token in `tokens`, leaving non-glob and non-matching
-// tokens untouched.
-pub fn expand_all(tokens: &[String]) -> Vec<String> {
-    tokens
-        .iter()
-        .flat_map(|token| expand(token).unwrap_or_else(|| vec![token.clone()]))
-        .collect()
+// Expands every glob token in `tokens` (patterns as produced by
+// `expand::expand_words`), leaving non-glob tokens as their literal text.
+// A pattern that matches nothing is normally kept literally too, as in
+// POSIX shells; with nullglob it's dropped instead, and with failglob the
+// whole expansion fails. The words are `OsString`s so that matched
+// filenames reach the command byte for byte.
+pub fn expand_all(tokens: &[String], options: &GlobOptions) -> Result<Vec<OsString>, String> {
+    let mut words = Vec::new();
+    for token in tokens {
+        match expand(token, options) {
+            Some(matches) => words.extend(matches),
+            None if !is_pattern(token) => words.push(unescape(token).into()),
+            None if options.failglob => return Err(format!("no match: {}", unescape(token))),
+            None if options.nullglob => {}
+            None => words.push(unescape(token).into()),
+        }
+    }
+    Ok(words)
 }
 
 #[cfg(test)]
@@ -114,14 +457,14 @@ mod tests {
 
     #[test]
     fn expand_returns_none_for_non_glob_token() {
-        assert_eq!(expand("plain.txt"), None);
+        assert_eq!(expand("plain.txt", &GlobOptions::default()), None);
     }
 
     #[test]
     fn expand_returns_none_when_nothing_matches() {
         let dir = temp_dir("no_match");
         let pattern = dir.join("*.zzz").to_string_lossy().to_string();
-        assert_eq!(expand(&pattern), None);
+        assert_eq!(expand(&pattern, &GlobOptions::default()), None);
         fs::remove_dir_all(&dir).unwrap();
     }
 
@@ -133,10 +476,10 @@ mod tests {
         fs::write(dir.join("c.md"), b"").unwrap();
 
         let pattern = dir.join("*.txt").to_string_lossy().to_string();
-        let matches = expand(&pattern).unwrap();
+        let matches = expand(&pattern, &GlobOptions::default()).unwrap();
         let expected = vec![
-            dir.join("a.txt").to_string_lossy().to_string(),
-            dir.join("b.txt").to_string_lossy().to_string(),
+            dir.join("a.txt").into_os_string(),
+            dir.join("b.txt").into_os_string(),
         ];
         assert_eq!(matches, expected);
         fs::remove_dir_all(&dir).unwrap();
@@ -149,25 +492,22 @@ mod tests {
         fs::write(dir.join("visible.txt"), b"").unwrap();
 
         let pattern = dir.join("*.txt").to_string_lossy().to_string();
-        let matches = expand(&pattern).unwrap();
-        assert_eq!(
-            matches,
-            vec![dir.join("visible.txt").to_string_lossy().to_string()]
-        );
+        let matches = expand(&pattern, &GlobOptions::default()).unwrap();
+        assert_eq!(matches, vec![dir.join("visible.txt").into_os_string()]);
 
         let dot_pattern = dir.join(".*.txt").to_string_lossy().to_string();
-        let dot_matches = expand(&dot_pattern).unwrap();
-        assert_eq!(
-            dot_matches,
-            vec![dir.join(".hidden.txt").to_string_lossy().to_string()]
-        );
+        let dot_matches = expand(&dot_pattern, &GlobOptions::default()).unwrap();
+        assert_eq!(dot_matches, vec![dir.join(".hidden.txt").into_os_string()]);
         fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
     fn expand_all_leaves_non_matching_and_non_glob_tokens_alone() {
         let tokens: Vec<String> = vec!["ls".to_string(), "-la".to_string(), "*.zzz".to_string()];
-        assert_eq!(expand_all(&tokens), tokens);
+        assert_eq!(
+            expand_all(&tokens, &GlobOptions::default()).unwrap(),
+            vec!["ls", "-la", "*.zzz"]
+        );
     }
 
     #[test]
@@ -178,15 +518,342 @@ mod tests {
 
         let pattern = dir.join("*.txt").to_string_lossy().to_string();
         let tokens = vec!["cat".to_string(), pattern];
-        let expanded = expand_all(&tokens);
+        let expanded = expand_all(&tokens, &GlobOptions::default()).unwrap();
         assert_eq!(
             expanded,
             vec![
-                "cat".to_string(),
-                dir.join("a.txt").to_string_lossy().to_string(),
-                dir.join("b.txt").to_string_lossy().to_string(),
+                OsString::from("cat"),
+                dir.join("a.txt").into_os_string(),
+                dir.join("b.txt").into_os_string(),
             ]
         );
         fs::remove_dir_all(&dir).unwrap();
     }
+
+    #[test]
+    fn wildcard_matches_bracket_sets_and_ranges() {
+        assert!(wildcard_matches("log[0-9].txt", "log3.txt"));
+        assert!(!wildcard_matches("log[0-9].txt", "logx.txt"));
+        assert!(!wildcard_matches("log[0-9].txt", "log10.txt"));
+        assert!(wildcard_matches("[abc]*", "beta"));
+        assert!(!wildcard_matches("[abc]*", "delta"));
+        assert!(wildcard_matches("[a-cx-z]", "y"));
+        assert!(wildcard_matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn wildcard_matches_negated_brackets() {
+        assert!(wildcard_matches("[!x]", "y"));
+        assert!(!wildcard_matches("[!x]", "x"));
+        assert!(wildcard_matches("[^0-9]*", "a1"));
+        assert!(!wildcard_matches("[^0-9]*", "1a"));
+    }
+
+    #[test]
+    fn wildcard_matches_posix_classes() {
+        assert!(wildcard_matches("[[:digit:]][[:upper:]]", "7Q"));
+        assert!(!wildcard_matches("[[:digit:]]", "a"));
+        assert!(wildcard_matches("[[:alpha:]_]*", "_x"));
+        assert!(wildcard_matches("[![:space:]]", "x"));
+        assert!(!wildcard_matches("[[:bogus:]]", "x"));
+    }
+
+    #[test]
+    fn wildcard_matches_bracket_edge_cases() {
+        // A leading `]` is a member, and an unclosed `[` is literal.
+        assert!(wildcard_matches("[]a]", "]"));
+        assert!(wildcard_matches("[!]]", "a"));
+        assert!(wildcard_matches("a[b", "a[b"));
+        assert!(wildcard_matches(r"[\]]", "]"));
+        assert!(wildcard_matches(r"\[a]", "[a]"));
+        assert!(!wildcard_matches("[a]", ""));
+    }
+
+    #[test]
+    fn is_pattern_needs_a_complete_bracket() {
+        assert!(is_pattern("file[12]"));
+        assert!(!is_pattern("file[12"));
+        assert!(!is_pattern(r"file\[12]"));
+    }
+
+    #[test]
+    fn wildcard_matches_escaped_characters_literally() {
+        assert!(wildcard_matches(r"a\*", "a*"));
+        assert!(!wildcard_matches(r"a\*", "ab"));
+        assert!(wildcard_matches(r"\?*", "?x"));
+        assert!(!wildcard_matches(r"\?*", "x"));
+    }
+
+    #[test]
+    fn unescape_removes_escaping_backslashes() {
+        assert_eq!(unescape(r"\*.txt"), "*.txt");
+        assert_eq!(unescape(r"a\\b"), r"a\b");
+    }
+
+    #[test]
+    fn expand_all_keeps_escaped_wildcards_literal() {
+        let dir = temp_dir("escaped");
+        fs::write(dir.join("a.txt"), b"").unwrap();
+        fs::write(dir.join("*.txt"), b"").unwrap();
+
+        let literal = format!(r"{}/\*.txt", dir.to_string_lossy());
+        assert_eq!(
+            expand_all(&[literal], &GlobOptions::default()).unwrap(),
+            vec![dir.join("*.txt").into_os_string()]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_unescapes_the_directory_part() {
+        let dir = temp_dir("star*dir");
+        fs::write(dir.join("a.txt"), b"").unwrap();
+
+        let escaped_dir = dir.to_string_lossy().replace('*', r"\*");
+        let pattern = format!("{escaped_dir}/*.txt");
+        assert_eq!(
+            expand(&pattern, &GlobOptions::default()),
+            Some(vec![dir.join("a.txt").into_os_string()])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn touch_all(dir: &Path, paths: &[&str]) {
+        for path in paths {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+    }
+
+    fn relative_matches(dir: &Path, pattern: &str, options: &GlobOptions) -> Vec<String> {
+        let prefix = format!("{}/", dir.to_string_lossy());
+        let full = format!("{prefix}{pattern}");
+        expand(&full, options)
+            .unwrap_or_default()
+            .iter()
+            .map(|path| {
+                path.to_string_lossy()
+                    .strip_prefix(&prefix)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_matches_wildcards_in_every_component() {
+        let dir = temp_dir("components");
+        touch_all(
+            &dir,
+            &[
+                "a/Cargo.toml",
+                "b/Cargo.toml",
+                "c/other.txt",
+                "src/x/tests/one.rs",
+                "src/y/tests/two.rs",
+                "src/y/tests/notes.md",
+            ],
+        );
+        let options = GlobOptions::default();
+        assert_eq!(
+            relative_matches(&dir, "*/Cargo.toml", &options),
+            vec!["a/Cargo.toml", "b/Cargo.toml"]
+        );
+        assert_eq!(
+            relative_matches(&dir, "src/*/tests/*.rs", &options),
+            vec!["src/x/tests/one.rs", "src/y/tests/two.rs"]
+        );
+        assert_eq!(
+            relative_matches(&dir, "*/", &options),
+            vec!["a/", "b/", "c/", "src/"]
+        );
+        assert!(relative_matches(&dir, "*/missing.txt", &options).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn globstar_recurses_only_when_enabled() {
+        let dir = temp_dir("globstar");
+        touch_all(
+            &dir,
+            &["top.rs", "a/mid.rs", "a/b/deep.rs", ".hidden/skip.rs"],
+        );
+
+        let off = GlobOptions::default();
+        assert_eq!(relative_matches(&dir, "**/*.rs", &off), vec!["a/mid.rs"]);
+
+        let on = GlobOptions {
+            globstar: true,
+            ..GlobOptions::default()
+        };
+        assert_eq!(
+            relative_matches(&dir, "**/*.rs", &on),
+            vec!["a/b/deep.rs", "a/mid.rs", "top.rs"]
+        );
+        assert_eq!(
+            relative_matches(&dir, "**", &on),
+            vec!["a", "a/b", "a/b/deep.rs", "a/mid.rs", "top.rs"]
+        );
+        assert_eq!(relative_matches(&dir, "a/**/", &on), vec!["a/", "a/b/"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nullglob_and_failglob_change_unmatched_patterns() {
+        let tokens = vec!["ls".to_string(), "*.zzz".to_string()];
+        let nullglob = GlobOptions {
+            nullglob: true,
+            ..GlobOptions::default()
+        };
+        assert_eq!(expand_all(&tokens, &nullglob).unwrap(), vec!["ls"]);
+
+        let failglob = GlobOptions {
+            failglob: true,
+            nullglob: true,
+            ..GlobOptions::default()
+        };
+        assert_eq!(
+            expand_all(&tokens, &failglob).unwrap_err(),
+            "no match: *.zzz"
+        );
+        // Words that aren't patterns are never affected.
+        let plain = vec![r"\*.zzz".to_string()];
+        assert_eq!(expand_all(&plain, &failglob).unwrap(), vec!["*.zzz"]);
+    }
+
+    #[test]
+    fn dotglob_matches_hidden_files() {
+        let dir = temp_dir("dotglob");
+        touch_all(&dir, &[".env", "main.rs", ".git/config", "src/lib.rs"]);
+        let dotglob = GlobOptions {
+            dotglob: true,
+            ..GlobOptions::default()
+        };
+        assert_eq!(
+            relative_matches(&dir, "*", &dotglob),
+            vec![".env", ".git", "main.rs", "src"]
+        );
+
+        let globstar = GlobOptions {
+            globstar: true,
+            ..dotglob
+        };
+        assert_eq!(
+            relative_matches(&dir, "**/*", &globstar),
+            vec![
+                ".env",
+                ".git",
+                ".git/config",
+                "main.rs",
+                "src",
+                "src/lib.rs"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nocaseglob_ignores_case() {
+        let dir = temp_dir("nocaseglob");
+        touch_all(&dir, &["README.md", "notes.MD", "Cargo.toml"]);
+        let nocase = GlobOptions {
+            nocaseglob: true,
+            ..GlobOptions::default()
+        };
+        assert_eq!(
+            relative_matches(&dir, "*.md", &nocase),
+            vec!["README.md", "notes.MD"]
+        );
+        assert_eq!(
+            relative_matches(&dir, "[c]argo.*", &nocase),
+            vec!["Cargo.toml"]
+        );
+        assert_eq!(
+            relative_matches(&dir, "*.md", &GlobOptions::default()),
+            vec!["README.md"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wildcard_matches_pathological_patterns_quickly() {
+        // Exponential with naive backtracking; linear-ish here.
+        let name = "a".repeat(100);
+        assert!(!wildcard_matches("*a*a*a*a*a*a*a*a*a*a*b", &name));
+        assert!(wildcard_matches("*a*a*a*a*a*a*a*a*a*a*", &name));
+        assert!(wildcard_matches("**a**", "a"));
+        assert!(wildcard_matches("*ab*cd", "xabyabcd"));
+        assert!(!wildcard_matches("*ab*cd", "xabycdab"));
+    }
+
+    #[test]
+    fn wildcard_matches_whole_characters() {
+        assert!(wildcard_matches("caf?", "café"));
+        assert!(wildcard_matches("[é]*", "été"));
+        assert!(!wildcard_matches("caf??", "café"));
+    }
+
+    #[test]
+    fn tokens_match_names_that_are_not_utf8() {
+        let name = b"bad\xffname.txt";
+        assert!(tokens_match(&compile("*.txt"), name, false));
+        assert!(tokens_match(&compile("bad?name.txt"), name, false));
+        assert!(tokens_match(&compile("bad[!a]name*"), name, false));
+        assert!(!tokens_match(&compile("bad[a-z]name*"), name, false));
+        assert!(!tokens_match(&compile("badname.txt"), name, false));
+    }
+
+    #[test]
+    fn expand_searches_directories_that_are_not_utf8() {
+        let dir = temp_dir("non_utf8");
+        let odd = dir.join(OsStr::from_bytes(b"d\xff"));
+        fs::create_dir(&odd).unwrap();
+        fs::write(odd.join("x.txt"), b"").unwrap();
+        fs::write(dir.join(OsStr::from_bytes(b"f\xfe.txt")), b"").unwrap();
+
+        assert_eq!(
+            relative_matches(&dir, "*/x.txt", &GlobOptions::default()),
+            vec!["d\u{FFFD}/x.txt"]
+        );
+        // The match comes back byte for byte, not with a replacement
+        // character that wouldn't name the file any more.
+        let pattern = format!("{}/f?.txt", dir.to_string_lossy());
+        assert_eq!(
+            expand(&pattern, &GlobOptions::default()),
+            Some(vec![dir
+                .join(OsStr::from_bytes(b"f\xfe.txt"))
+                .into_os_string()])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // A rough benchmark rather than a test, so it only runs when asked:
+    // `cargo test --release bench_ -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_expand_large_directory() {
+        use std::time::Instant;
+
+        let dir = temp_dir("bench");
+        for i in 0..20_000 {
+            fs::write(
+                dir.join(format!("file{i:05}.{}", ["rs", "txt", "md"][i % 3])),
+                b"",
+            )
+            .unwrap();
+        }
+        let options = GlobOptions::default();
+        for pattern in ["*.rs", "file1*[0-9].txt", "*a*a*a*a*a*b", "*[[:upper:]]*"] {
+            let start = Instant::now();
+            let count = relative_matches(&dir, pattern, &options).len();
+            println!("{pattern:>20}: {count:>6} matches in {:?}", start.elapsed());
+        }
+
+        let name = "a".repeat(10_000);
+        let start = Instant::now();
+        assert!(!wildcard_matches("*a*a*a*a*a*a*a*a*b", &name));
+        println!("pathological match on 10k chars: {:?}", start.elapsed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,501 @@
+use crate::parser::tokenize;
+use crate::vars::Variables;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many entries are kept when $HISTSIZE isn't set to a number.
+const DEFAULT_SIZE: usize = 500;
+
+// Holds an flock(2) lock on a file until dropped, so that shells sharing a
+// history file take turns reading and rewriting it.
+struct FileLock<'a>(&'a File);
+
+impl FileLock<'_> {
+    fn acquire(file: &File, operation: libc::c_int) -> io::Result<FileLock<'_>> {
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(FileLock(file));
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::flock(self.0.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+}
+
+// The most lines to keep, from $HISTSIZE: None (no limit) if it's
+// negative, and the default if it's unset or not a number.
+fn size(vars: &Variables) -> Option<usize> {
+    match vars.get("HISTSIZE").map(|s| s.trim().parse::<i64>()) {
+        Some(Ok(n)) if n < 0 => None,
+        Some(Ok(n)) => Some(usize::try_from(n).unwrap_or(usize::MAX)),
+        _ => Some(DEFAULT_SIZE),
+    }
+}
+
+// The file history is saved to, from $HISTFILE. Unsetting it, or setting
+// it to an empty string, stops history being saved.
+fn file_path(vars: &Variables) -> Option<PathBuf> {
+    vars.get("HISTFILE")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+// Drops all but the last `size` of `lines`.
+fn trim<T>(lines: &mut Vec<T>, size: Option<usize>) {
+    if let Some(size) = size {
+        if lines.len() > size {
+            lines.drain(..lines.len() - size);
+        }
+    }
+}
+
+// An entry of the history file: the `#<seconds>` line written before it,
+// if there is one, and its text.
+type FileEntry = (Option<String>, String);
+
+// Whether `line` is a `#` followed by digits, the timestamp bash writes
+// before each entry.
+fn is_timestamp(line: &str) -> bool {
+    line.strip_prefix('#')
+        .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Splits the contents of a history file into entries. An entry after a
+// timestamp runs up to the next one, so a command with newlines in it
+// comes back whole; lines before the first timestamp, as older versions
+// wrote them, are an entry each.
+fn parse_file(contents: &str) -> Vec<FileEntry> {
+    let mut entries: Vec<FileEntry> = Vec::new();
+    let mut stamped = false;
+    for line in contents.lines() {
+        if is_timestamp(line) {
+            entries.push((Some(line.to_string()), String::new()));
+            stamped = true;
+        } else if stamped {
+            let (_, text) = entries.last_mut().expect("stamped entry");
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(line);
+        } else if !line.trim().is_empty() {
+            entries.push((None, line.to_string()));
+        }
+    }
+    entries.retain(|(_, text)| !text.trim().is_empty());
+    entries
+}
+
+// Writes `entry` in the form `parse_file` reads back.
+fn write_entry(mut out: impl Write, (stamp, text): &FileEntry) -> io::Result<()> {
+    if let Some(stamp) = stamp {
+        writeln!(out, "{stamp}")?;
+    }
+    writeln!(out, "{text}")
+}
+
+// The last word of `line`, for `!$`, taking quoting into account where the
+// line can be tokenized.
+fn last_word(line: &str) -> Option<String> {
+    match tokenize(line) {
+        Ok(tokens) => tokens.last().cloned(),
+        Err(_) => line.split_whitespace().last().map(str::to_string),
+    }
+}
+
+// The lines entered so far, oldest first. Entries are numbered from 1 by
+// `history` and `!n`.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+}
+
+impl History {
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // Reads the saved history from $HISTFILE, first setting it to
+    // ~/.0shell_history if it isn't set at all.
+    pub fn load(&mut self, vars: &mut Variables) {
+        if vars.get("HISTFILE").is_none() {
+            if let Some(home) = vars.get("HOME") {
+                let path = PathBuf::from(home).join(".0shell_history");
+                let _ = vars.set("HISTFILE", &path.to_string_lossy());
+            }
+        }
+        let Some(path) = file_path(vars) else {
+            return;
+        };
+        let Ok(file) = File::open(path) else {
+            return;
+        };
+        let mut contents = Vec::new();
+        if let Ok(_lock) = FileLock::acquire(&file, libc::LOCK_SH) {
+            let _ = (&file).read_to_end(&mut contents);
+        }
+        self.entries = parse_file(&String::from_utf8_lossy(&contents))
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect();
+        trim(&mut self.entries, size(vars));
+    }
+
+    // Records `line`, subject to $HISTCONTROL (a colon-separated list of
+    // `ignorespace`, `ignoredups`, `ignoreboth` and `erasedups`), then
+    // appends it to $HISTFILE. Blank lines are never recorded.
+    pub fn add(&mut self, line: &str, vars: &Variables) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let control = vars.get("HISTCONTROL").unwrap_or("");
+        let control: Vec<&str> = control.split(':').collect();
+        let has = |option: &str| control.contains(&option) || control.contains(&"ignoreboth");
+        if has("ignorespace") && line.starts_with([' ', '\t']) {
+            return;
+        }
+        if has("ignoredups") && self.entries.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        if control.contains(&"erasedups") {
+            self.entries.retain(|entry| entry != line);
+        }
+        self.entries.push(line.to_string());
+        trim(&mut self.entries, size(vars));
+
+        if let Some(path) = file_path(vars) {
+            if let Err(e) = append_to_file(&path, line, size(vars)) {
+                eprintln!("history: {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // Removes entry number `n`.
+    pub fn delete(&mut self, n: usize) -> Result<(), String> {
+        if n == 0 || n > self.entries.len() {
+            return Err(format!("{n}: history position out of range"));
+        }
+        self.entries.remove(n - 1);
+        Ok(())
+    }
+
+    // Performs history expansion on a line just entered, returning None if
+    // there was nothing to expand:
+    //
+    //   !!         the previous line
+    //   !n, !-n    line number n, or the nth line back
+    //   !prefix    the most recent line starting with prefix
+    //   !$         the last word of the previous line
+    //   ^old^new   the previous line with old replaced by new
+    //
+    // A `!` inside single quotes, after a backslash or `[`, or before a
+    // space, `=` or `(` is left alone.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        if let Some(rest) = line.strip_prefix('^') {
+            return self.substitute(rest).map(Some);
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut out = String::new();
+        let mut changed = false;
+        let mut in_single = false;
+        let mut in_double = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\\' if !in_single => {
+                    out.extend(&chars[i..(i + 2).min(chars.len())]);
+                    i += 2;
+                    continue;
+                }
+                '\'' if !in_double => in_single = !in_single,
+                '"' if !in_single => in_double = !in_double,
+                '!' if !in_single && (i == 0 || chars[i - 1] != '[') => {
+                    if let Some((text, len)) = self.event(&chars[i + 1..])? {
+                        out.push_str(&text);
+                        changed = true;
+                        i += 1 + len;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            out.push(c);
+            i += 1;
+        }
+        Ok(changed.then_some(out))
+    }
+
+    // Looks up the event designator at the start of `rest` (the text after
+    // a `!`), returning the text it stands for and how many characters it
+    // took up, or None if it isn't one.
+    fn event(&self, rest: &[char]) -> Result<Option<(String, usize)>, String> {
+        let (found, len) = match rest.first() {
+            None | Some(' ' | '\t' | '=' | '(' | '"') => return Ok(None),
+            Some('!') => (self.entries.last().cloned(), 1),
+            Some('$') => (self.entries.last().and_then(|line| last_word(line)), 1),
+            Some(_) => {
+                let len = rest
+                    .iter()
+                    .position(|c| c.is_whitespace() || ";&|<>()'\"`".contains(*c))
+                    .unwrap_or(rest.len());
+                let word: String = rest[..len].iter().collect();
+                let found = if let Ok(back) = word.strip_prefix('-').unwrap_or("x").parse::<usize>()
+                {
+                    back.checked_sub(1)
+                        .and_then(|back| self.entries.iter().rev().nth(back))
+                        .cloned()
+                } else if let Ok(n) = word.parse::<usize>() {
+                    n.checked_sub(1).and_then(|i| self.entries.get(i)).cloned()
+                } else {
+                    self.entries
+                        .iter()
+                        .rev()
+                        .find(|e| e.starts_with(&word))
+                        .cloned()
+                };
+                (found, len)
+            }
+        };
+        match found {
+            Some(text) => Ok(Some((text, len))),
+            None => {
+                let designator: String = rest[..len].iter().collect();
+                Err(format!("!{designator}: event not found"))
+            }
+        }
+    }
+
+    // `^old^new^rest`: the previous line with the first `old` replaced by
+    // `new`, followed by anything after the closing `^`.
+    fn substitute(&self, spec: &str) -> Result<String, String> {
+        let mut parts = spec.splitn(3, '^');
+        let old = parts.next().unwrap_or("");
+        let new = parts.next().unwrap_or("");
+        let rest = parts.next().unwrap_or("");
+        match self.entries.last() {
+            Some(previous) if !old.is_empty() && previous.contains(old) => {
+                Ok(format!("{}{rest}", previous.replacen(old, new, 1)))
+            }
+            _ => Err(format!("^{old}^{new}: substitution failed")),
+        }
+    }
+}
+
+// Appends `line` to the history file at `path` under an exclusive lock,
+// after a timestamp line so that any newlines in it stay part of the one
+// entry, then cuts the file down to its last `size` entries if it has
+// grown past them. Other shells' entries, appended in between, are kept.
+fn append_to_file(path: &PathBuf, line: &str, size: Option<usize>) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let _lock = FileLock::acquire(&file, libc::LOCK_EX)?;
+    let mut handle = &file;
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    write_entry(handle, &(Some(format!("#{seconds}")), line.to_string()))?;
+
+    let Some(size) = size else {
+        return Ok(());
+    };
+    let mut contents = Vec::new();
+    handle.seek(SeekFrom::Start(0))?;
+    handle.read_to_end(&mut contents)?;
+    let mut entries = parse_file(&String::from_utf8_lossy(&contents));
+    if entries.len() > size {
+        trim(&mut entries, Some(size));
+        file.set_len(0)?;
+        for entry in &entries {
+            write_entry(handle, entry)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "zero_shell_history_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn vars_with(pairs: &[(&str, &str)]) -> Variables {
+        let mut vars = Variables::default();
+        for (name, value) in pairs {
+            vars.set(name, value).unwrap();
+        }
+        vars
+    }
+
+    fn history_of(lines: &[&str]) -> History {
+        let vars = Variables::default();
+        let mut history = History::default();
+        for line in lines {
+            history.add(line, &vars);
+        }
+        history
+    }
+
+    #[test]
+    fn expand_replaces_event_designators() {
+        let history = history_of(&["ls -l src", "echo one two", "cat 'a file'"]);
+        let expand = |line| history.expand(line).unwrap();
+        assert_eq!(expand("!! | wc"), Some("cat 'a file' | wc".to_string()));
+        assert_eq!(expand("!1"), Some("ls -l src".to_string()));
+        assert_eq!(expand("!-2"), Some("echo one two".to_string()));
+        assert_eq!(expand("!ec;!l"), Some("echo one two;ls -l src".to_string()));
+        assert_eq!(expand("ls !$"), Some("ls 'a file'".to_string()));
+        assert_eq!(expand("^cat^rm"), Some("rm 'a file'".to_string()));
+        assert_eq!(
+            expand("^a f^the f^ x"),
+            Some("cat 'the file' x".to_string())
+        );
+    }
+
+    #[test]
+    fn expand_leaves_quoted_and_plain_bangs_alone() {
+        let history = history_of(&["true"]);
+        for line in [
+            "echo hi",
+            "echo '!!'",
+            "echo \\!!",
+            "! true",
+            "ls [!a]*",
+            "((a != 1))",
+        ] {
+            assert_eq!(history.expand(line).unwrap(), None, "{line}");
+        }
+        assert_eq!(
+            history.expand("echo \"!!\"").unwrap(),
+            Some("echo \"true\"".to_string())
+        );
+    }
+
+    #[test]
+    fn expand_reports_missing_events() {
+        let history = history_of(&["true"]);
+        assert_eq!(
+            history.expand("!nope x"),
+            Err("!nope: event not found".to_string())
+        );
+        assert_eq!(history.expand("!5"), Err("!5: event not found".to_string()));
+        assert_eq!(
+            history.expand("^x^y"),
+            Err("^x^y: substitution failed".to_string())
+        );
+    }
+
+    #[test]
+    fn add_follows_histcontrol_and_histsize() {
+        let vars = vars_with(&[("HISTCONTROL", "ignoreboth"), ("HISTSIZE", "3")]);
+        let mut history = History::default();
+        for line in ["a", "a", " secret", "", "b", "c", "d"] {
+            history.add(line, &vars);
+        }
+        assert_eq!(history.entries(), ["b", "c", "d"]);
+
+        let vars = vars_with(&[("HISTCONTROL", "erasedups")]);
+        let mut history = History::default();
+        for line in ["a", "b", "a"] {
+            history.add(line, &vars);
+        }
+        assert_eq!(history.entries(), ["b", "a"]);
+    }
+
+    #[test]
+    fn delete_checks_the_position() {
+        let mut history = history_of(&["a", "b", "c"]);
+        history.delete(2).unwrap();
+        assert_eq!(history.entries(), ["a", "c"]);
+        assert!(history.delete(3).is_err());
+        assert!(history.delete(0).is_err());
+    }
+
+    #[test]
+    fn history_is_saved_to_and_loaded_from_histfile() {
+        let dir = temp_dir("save");
+        let file = dir.join("history");
+        let mut vars = vars_with(&[("HISTFILE", file.to_str().unwrap()), ("HISTSIZE", "2")]);
+        let mut first = History::default();
+        let mut second = History::default();
+        first.add("one", &vars);
+        second.add("two", &vars);
+        first.add("three", &vars);
+        let contents = fs::read_to_string(&file).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4, "{contents}");
+        assert!(
+            is_timestamp(lines[0]) && is_timestamp(lines[2]),
+            "{contents}"
+        );
+        assert_eq!((lines[1], lines[3]), ("two", "three"));
+
+        let mut loaded = History::default();
+        loaded.load(&mut vars);
+        assert_eq!(loaded.entries(), ["two", "three"]);
+    }
+
+    #[test]
+    fn load_defaults_histfile_to_the_home_directory() {
+        let dir = temp_dir("default");
+        let mut vars = vars_with(&[("HOME", dir.to_str().unwrap())]);
+        History::default().load(&mut vars);
+        let expected = dir.join(".0shell_history");
+        assert_eq!(vars.get("HISTFILE"), expected.to_str());
+    }
+
+    #[test]
+    fn histfile_keeps_multi_line_commands_whole() {
+        let dir = temp_dir("multi_line");
+        let file = dir.join("history");
+        let mut vars = vars_with(&[("HISTFILE", file.to_str().unwrap()), ("HISTSIZE", "2")]);
+        let mut history = History::default();
+        for line in ["echo \"a\n\nb\"", "#not a stamp\nls", "true"] {
+            history.add(line, &vars);
+        }
+        let mut loaded = History::default();
+        loaded.load(&mut vars);
+        assert_eq!(loaded.entries(), ["#not a stamp\nls", "true"]);
+    }
+
+    #[test]
+    fn load_reads_files_without_timestamps_a_line_at_a_time() {
+        let dir = temp_dir("old_format");
+        let file = dir.join("history");
+        fs::write(&file, "one\n\ntwo\n#1700000000\nthree\nfour\n").unwrap();
+        let mut vars = vars_with(&[("HISTFILE", file.to_str().unwrap())]);
+        let mut loaded = History::default();
+        loaded.load(&mut vars);
+        assert_eq!(loaded.entries(), ["one", "two", "three\nfour"]);
+    }
+}
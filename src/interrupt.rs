@@ -0,0 +1,80 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set when an interactive shell gets SIGINT (Ctrl-C), and cleared before
+// each pipeline runs.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Makes SIGINT set the interrupted flag instead of ending the shell. The
+// handler is installed without SA_RESTART, so a read or write a built-in
+// is blocked in fails with EINTR and the built-in gets to see the flag.
+// Children get the default action back when they exec, as they do for
+// any caught signal.
+pub fn catch() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+// Whether Ctrl-C has been pressed since the current pipeline started.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+// Blocks SIGINT on the calling thread, or unblocks it again. Threads
+// started while it's blocked start with it blocked too, which lets a
+// pipeline choose the one stage the signal is delivered to (and so the
+// one whose blocked read it breaks into).
+pub fn block(blocked: bool) {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        let how = if blocked {
+            libc::SIG_BLOCK
+        } else {
+            libc::SIG_UNBLOCK
+        };
+        libc::pthread_sigmask(how, &set, std::ptr::null_mut());
+    }
+}
+
+// Copies `reader` to `writer` like `io::copy`, except that it gives up with
+// an `Interrupted` error once Ctrl-C has been pressed, rather than retrying
+// the read or write the signal broke into.
+pub fn copy(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<u64> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        if interrupted() {
+            return Err(io::Error::from(io::ErrorKind::Interrupted));
+        }
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut chunk = &buffer[..read];
+        while !chunk.is_empty() {
+            match writer.write(chunk) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(written) => chunk = &chunk[written..],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted && !interrupted() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        copied += read as u64;
+    }
+}
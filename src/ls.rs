@@ -1,3 +1,4 @@
+use crate::interrupt;
 use crate::users::{get_group_name_by_gid, get_user_name_by_uid};
 use chrono::{Local, TimeZone};
 use libc::mode_t;
@@ -70,6 +71,7 @@ fn get_file_classification_char(metadata: &Metadata) -> String {
 
 // When printing the total, consider how you want to represent this total in terms of your filesystem's block size.
 // The division or adjustment might be needed if you're converting between block sizes or aligning with how `ls` reports its total.
+// Returns false if anything (the directory itself or any entry) couldn't be read.
 pub fn list_directory(
     dir: &Path,
     long_format: bool,
@@ -77,14 +79,16 @@ pub fn list_directory(
     classify: bool,
     recursive: bool,
     output: &mut dyn Write,
-) {
+    error: &mut dyn Write,
+) -> bool {
     let read_dir = match fs::read_dir(dir) {
         Ok(read_dir) => read_dir,
         Err(e) => {
-            eprintln!("ls: cannot access '{}': {}", dir.display(), e);
-            return;
+            let _ = writeln!(error, "ls: cannot access '{}': {}", dir.display(), e);
+            return false;
         }
     };
+    let mut ok = true;
 
     let mut entries: Vec<_> = read_dir
         .filter_map(Result::ok)
@@ -108,14 +112,14 @@ pub fn list_directory(
     });
 
     if long_format && all {
-        let total_blocks = calculate_total_blocks(dir, all);
+        let total_blocks = calculate_total_blocks(dir, all, error);
         let _ = writeln!(output, "total {total_blocks}");
 
         // Manually print '.' and '..' with their metadata
-        print_metadata(dir, true, classify, output); // Current directory '.'
-        print_metadata(&dir.join(".."), true, classify, output); // Parent directory '..'
+        print_metadata(dir, true, classify, output, error); // Current directory '.'
+        print_metadata(&dir.join(".."), true, classify, output, error); // Parent directory '..'
     } else if long_format && !all {
-        let total_blocks = calculate_total_blocks(dir, all);
+        let total_blocks = calculate_total_blocks(dir, all, error);
         let _ = writeln!(output, "total {total_blocks}");
     }
 
@@ -137,7 +141,8 @@ pub fn list_directory(
         let metadata = match entry.metadata() {
             Ok(metadata) => metadata,
             Err(e) => {
-                eprintln!("ls: cannot access '{}': {}", path.display(), e);
+                let _ = writeln!(error, "ls: cannot access '{}': {}", path.display(), e);
+                ok = false;
                 continue;
             }
         };
@@ -165,19 +170,38 @@ pub fn list_directory(
             .collect();
 
         for subdir in subdirs {
+            if interrupt::interrupted() {
+                return false;
+            }
             let _ = writeln!(output);
             let _ = writeln!(output, "{}:", subdir.display());
-            list_directory(&subdir, long_format, all, classify, recursive, output);
+            ok &= list_directory(
+                &subdir,
+                long_format,
+                all,
+                classify,
+                recursive,
+                output,
+                error,
+            );
         }
     }
+
+    ok
 }
 
-fn print_metadata(path: &Path, long_format: bool, classify: bool, output: &mut dyn Write) {
+fn print_metadata(
+    path: &Path,
+    long_format: bool,
+    classify: bool,
+    output: &mut dyn Write,
+    error: &mut dyn Write,
+) {
     if long_format {
         let metadata = match fs::metadata(path) {
             Ok(metadata) => metadata,
             Err(e) => {
-                eprintln!("ls: cannot access '{}': {}", path.display(), e);
+                let _ = writeln!(error, "ls: cannot access '{}': {}", path.display(), e);
                 return;
             }
         };
@@ -189,7 +213,7 @@ fn print_metadata(path: &Path, long_format: bool, classify: bool, output: &mut d
     }
 }
 
-fn calculate_total_blocks(dir: &Path, all: bool) -> u64 {
+fn calculate_total_blocks(dir: &Path, all: bool, error: &mut dyn Write) -> u64 {
     let mut total_blocks = 0.0;
 
     // `ls` reports totals in 1024-byte blocks; `st_blocks` (from stat) is in 512-byte units.
@@ -198,7 +222,7 @@ fn calculate_total_blocks(dir: &Path, all: bool) -> u64 {
     let entries = match fs::read_dir(dir) {
         Ok(entries) => entries,
         Err(e) => {
-            eprintln!("ls: cannot access '{}': {}", dir.display(), e);
+            let _ = writeln!(error, "ls: cannot access '{}': {}", dir.display(), e);
             return 0;
         }
     };
@@ -216,7 +240,12 @@ fn calculate_total_blocks(dir: &Path, all: bool) -> u64 {
         let metadata = match entry.metadata() {
             Ok(metadata) => metadata,
             Err(e) => {
-                eprintln!("ls: cannot access '{}': {}", entry.path().display(), e);
+                let _ = writeln!(
+                    error,
+                    "ls: cannot access '{}': {}",
+                    entry.path().display(),
+                    e
+                );
                 continue;
             }
         };
@@ -397,7 +426,15 @@ mod tests {
         fs::write(dir.join("sub/inner.txt"), b"").unwrap();
 
         let mut output = Vec::new();
-        list_directory(&dir, false, false, false, true, &mut output);
+        list_directory(
+            &dir,
+            false,
+            false,
+            false,
+            true,
+            &mut output,
+            &mut std::io::sink(),
+        );
         let text = String::from_utf8(output).unwrap();
 
         assert!(text.contains(&format!("{}:", dir.join("sub").display())));
@@ -413,7 +450,15 @@ mod tests {
         fs::write(dir.join("sub/inner.txt"), b"").unwrap();
 
         let mut output = Vec::new();
-        list_directory(&dir, false, false, false, false, &mut output);
+        list_directory(
+            &dir,
+            false,
+            false,
+            false,
+            false,
+            &mut output,
+            &mut std::io::sink(),
+        );
         let text = String::from_utf8(output).unwrap();
 
         assert!(!text.contains("inner.txt"));
@@ -1,284 +1,305 @@
+mod arith;
+mod brace;
+mod builtins;
+mod completion;
+mod editor;
+mod exec;
+mod expand;
+mod external;
 mod fileops;
 mod glob;
+mod history;
+mod interrupt;
 mod ls;
 mod parser;
+mod prompt;
+mod redirect;
 mod users;
+mod vars;
 
-use fileops::{copy_file, move_item, remove_item};
-use ls::{list_directory, list_directory_entry};
-use parser::{parse_flags, parse_pipeline, tokenize, Redirect};
+use completion::complete;
+use editor::{Editor, Entered};
+use exec::{run_list, Shell};
+use parser::{continuation, heredocs_mut, parse_list, tokenize, AndOrList, Continuation};
 use std::env;
-use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
-use std::path::Path;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::os::fd::FromRawFd;
 use std::process::exit;
 
-// Shared by the cp and mv handlers: a single source/destination pair
-// behaves as before, but with more than one source the last argument
-// must be an existing directory that every source gets copied/moved into.
-fn copy_or_move_many(args: &[&str], label: &str, op: impl Fn(&Path, &Path) -> Result<(), String>) {
-    if args.len() < 2 {
-        eprintln!("{label}: missing file operand");
-        return;
-    }
-
-    if args.len() == 2 {
-        let source = Path::new(args[0]);
-        let destination = Path::new(args[1]);
-        if let Err(e) = op(source, destination) {
-            eprintln!("{}: {}: {}", label, source.display(), e);
-        }
-        return;
-    }
+// Where commands come from: the line editor, when stdin is a terminal, or
+// else the lines of a script file, a `-c` string or whatever is piped in.
+enum Input {
+    Terminal(Editor),
+    Lines(Box<dyn BufRead>),
+}
 
-    let destination = Path::new(args[args.len() - 1]);
-    if !destination.is_dir() {
-        eprintln!(
-            "{}: target '{}' is not a directory",
-            label,
-            destination.display()
-        );
-        return;
+impl Input {
+    fn is_interactive(&self) -> bool {
+        matches!(self, Input::Terminal(_))
     }
 
-    for source in &args[..args.len() - 1] {
-        let source = Path::new(source);
-        if let Err(e) = op(source, destination) {
-            eprintln!("{}: {}: {}", label, source.display(), e);
+    // Reads the next line, without its newline, after showing the prompt
+    // that `prompt` renders if interactive.
+    fn read_line(
+        &mut self,
+        prompt: fn(&Shell) -> String,
+        history: &[String],
+        shell: &Shell,
+    ) -> Entered {
+        match self {
+            Input::Terminal(editor) => editor
+                .read_line(&prompt(shell), history, &|text| complete(text, shell))
+                .unwrap_or(Entered::EndOfInput),
+            Input::Lines(reader) => {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) => Entered::EndOfInput,
+                    Ok(_) => {
+                        if line.ends_with('\n') {
+                            line.pop();
+                        }
+                        Entered::Line(line)
+                    }
+                }
+            }
         }
     }
 }
 
-// Runs a single command, writing its normal output to `output` (so callers
-// can redirect it to a file or capture it for a pipeline) and any piped-in
-// input via `input`. Errors always go to the real stderr, regardless of
-// where `output` points, matching how redirection/piping normally only
-// affects stdout.
-fn execute_command(
-    command: &str,
-    args: &[&str],
-    input: &str,
-    output: &mut dyn Write,
-    previous_dir: &mut Option<String>,
-) {
-    match command {
-        "cd" => {
-            let new_dir = if args.first().copied() == Some("-") {
-                let Some(prev) = previous_dir.clone() else {
-                    eprintln!("cd: OLDPWD not set");
-                    return;
-                };
-                prev
-            } else if let Some(dir) = args.first() {
-                dir.to_string()
-            } else {
-                let Ok(home) = env::var("HOME") else {
-                    eprintln!("cd: HOME not set");
-                    return;
-                };
-                home
-            };
-
-            let current = env::current_dir().ok();
-            if let Err(e) = env::set_current_dir(Path::new(&new_dir)) {
-                eprintln!("cd: {new_dir}: {e}");
-                return;
-            }
-            if let Some(current) = current {
-                *previous_dir = Some(current.display().to_string());
-            }
-            if args.first().copied() == Some("-") {
-                let _ = writeln!(output, "{new_dir}");
-            }
-        }
-        "exit" => exit(0),
-        "echo" => {
-            let echo_str = args.join(" ");
-            let _ = writeln!(output, "{echo_str}");
-        }
-        "pwd" => {
-            let _ = writeln!(output, "{}", env::current_dir().unwrap().display());
-        }
-        "cat" => {
-            if args.is_empty() {
-                if input.is_empty() {
-                    eprintln!("cat: No file specified");
-                } else {
-                    let _ = write!(output, "{input}");
+// Reads the bodies of any here-documents on the line just entered from the
+// lines that follow it, each up to its delimiter line. Returns false if
+// Ctrl-C cancelled the command instead.
+fn read_heredoc_bodies(list: &mut [AndOrList], input: &mut Input, shell: &Shell) -> bool {
+    for heredoc in heredocs_mut(list) {
+        loop {
+            let line = match input.read_line(prompt::secondary, &[], shell) {
+                Entered::Line(line) => line,
+                Entered::Cancelled => return false,
+                Entered::EndOfInput => {
+                    eprintln!(
+                        "warning: here-document delimited by end-of-file (wanted '{}')",
+                        heredoc.delimiter
+                    );
+                    break;
                 }
-            } else {
-                for filename in args {
-                    match std::fs::read_to_string(filename) {
-                        Ok(contents) => {
-                            let _ = write!(output, "{contents}");
-                        }
-                        Err(e) => eprintln!("cat: {filename}: {e}"),
-                    }
+            };
+            match heredoc.body_line(&line) {
+                Some(line) => {
+                    heredoc.body.push_str(line);
+                    heredoc.body.push('\n');
                 }
+                None => break,
             }
         }
-        "ls" => {
-            let parsed_args = parse_flags(args);
-            let long_format = parsed_args.contains(&"-l".to_string());
-            let all = parsed_args.contains(&"-a".to_string());
-            let classify = parsed_args.contains(&"-F".to_string());
-            let recursive = parsed_args.contains(&"-R".to_string());
-            let paths: Vec<&String> = parsed_args.iter().filter(|a| !a.starts_with('-')).collect();
+    }
+    true
+}
 
-            if paths.is_empty() {
-                list_directory(
-                    Path::new("."),
-                    long_format,
-                    all,
-                    classify,
-                    recursive,
-                    output,
-                );
-            } else {
-                let show_headers = paths.len() > 1;
-                for (i, p) in paths.iter().enumerate() {
-                    let path = Path::new(p.as_str());
-                    match fs::metadata(path) {
-                        Ok(metadata) if metadata.is_dir() => {
-                            if show_headers {
-                                if i > 0 {
-                                    let _ = writeln!(output);
-                                }
-                                let _ = writeln!(output, "{p}:");
-                            }
-                            list_directory(path, long_format, all, classify, recursive, output);
-                        }
-                        Ok(metadata) => {
-                            let _ = writeln!(
-                                output,
-                                "{}",
-                                list_directory_entry(path, &metadata, classify, long_format)
-                            );
-                        }
-                        Err(e) => eprintln!("ls: cannot access '{p}': {e}"),
-                    }
-                }
+// Reads more lines at the $PS2 prompt for as long as the command begun in
+// `command` is unfinished (see `continuation`), joining them on. At end of
+// input the command is left unfinished for the parser to report. Returns
+// false if Ctrl-C cancelled the command instead.
+fn read_continuation_lines(command: &mut String, input: &mut Input, shell: &Shell) -> bool {
+    while let Some(continuation) = continuation(command) {
+        let line = match input.read_line(prompt::secondary, &[], shell) {
+            Entered::Line(line) => line,
+            Entered::Cancelled => return false,
+            Entered::EndOfInput => break,
+        };
+        match continuation {
+            Continuation::Backslash => {
+                command.pop();
             }
+            Continuation::Operator => command.push(' '),
+            Continuation::Newline => command.push('\n'),
         }
-        "rm" => {
-            let mut recursive = false;
-            let mut files = Vec::new();
+        command.push_str(&line);
+    }
+    true
+}
 
-            for &arg in args {
-                if arg == "-r" {
-                    recursive = true;
-                } else {
-                    files.push(arg);
-                }
-            }
+// Runs $PROMPT_COMMAND, if it's set, before a prompt is shown. `$?`
+// afterwards is still the status of the last command entered.
+fn run_prompt_command(shell: &mut Shell) {
+    let Some(command) = shell.vars.get("PROMPT_COMMAND").map(str::to_string) else {
+        return;
+    };
+    let status = shell.last_status;
+    match tokenize(&command).and_then(|tokens| parse_list(&tokens)) {
+        Ok(list) => run_list(&list, shell),
+        Err(e) => eprintln!("{e}"),
+    }
+    shell.last_status = status;
+}
 
-            if files.is_empty() {
-                eprintln!("rm: missing operand");
+// Sets up the session from the command line, bash-style:
+//
+//   zero_shell                               read commands from stdin
+//   zero_shell script [args...]              run a script file
+//   zero_shell -c command [name [args...]]   run a single command string
+//
+// `$0` is the script (or `name`), and the arguments after it are the
+// positional parameters. Exits with status 2 for a bad option and 127
+// (126 if it exists) for a script that can't be opened.
+fn start(shell: &mut Shell) -> Input {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "zero_shell".to_string());
+    let args: Vec<String> = args.collect();
+    let usage_error = |message: String| -> ! {
+        eprintln!("{program}: {message}");
+        eprintln!("usage: {program} [-c command [name [args...]] | script [args...]]");
+        exit(2);
+    };
+
+    let mut rest = args.as_slice();
+    if rest.first().is_some_and(|arg| arg == "--") {
+        rest = &rest[1..];
+    }
+    let (name, input) = match rest.first().map(String::as_str) {
+        None => {
+            let is_tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+            let input = if is_tty {
+                Input::Terminal(Editor::new())
             } else {
-                for file in files {
-                    let path = Path::new(file);
-                    if let Err(e) = remove_item(path, recursive) {
-                        eprintln!("rm: {file}: {e}");
-                    }
+                // Read stdin a byte at a time, so that none of what's meant
+                // for the commands being run ends up in our buffer instead.
+                let stdin = unsafe { File::from_raw_fd(libc::STDIN_FILENO) };
+                Input::Lines(Box::new(BufReader::with_capacity(1, stdin)))
+            };
+            (program.clone(), input)
+        }
+        Some("-c") => {
+            let Some(command) = rest.get(1) else {
+                usage_error("-c: option requires an argument".to_string());
+            };
+            rest = &rest[2..];
+            let name = match rest.split_first() {
+                Some((name, args)) => {
+                    rest = args;
+                    name.clone()
                 }
-            }
+                None => program.clone(),
+            };
+            (name, Input::Lines(Box::new(Cursor::new(command.clone()))))
         }
-        "cp" => copy_or_move_many(args, "cp", copy_file),
-        "mv" => copy_or_move_many(args, "mv", move_item),
-        "mkdir" => {
-            let parsed_args = parse_flags(args);
-            let make_parents = parsed_args.contains(&"-p".to_string());
-            let dirs: Vec<&String> = parsed_args.iter().filter(|a| !a.starts_with('-')).collect();
-
-            if dirs.is_empty() {
-                eprintln!("mkdir: missing operand");
-            } else {
-                for dir_name in dirs {
-                    let path = Path::new(dir_name);
-                    let result = if make_parents {
-                        fs::create_dir_all(path)
+        Some(option) if option.starts_with('-') && option != "-" => {
+            usage_error(format!("{option}: invalid option"));
+        }
+        Some(script) => {
+            let file = match File::open(script) {
+                Ok(file) if file.metadata().is_ok_and(|m| m.is_dir()) => {
+                    eprintln!("{program}: {script}: Is a directory");
+                    exit(126);
+                }
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("{program}: {script}: {e}");
+                    exit(if e.kind() == io::ErrorKind::NotFound {
+                        127
                     } else {
-                        fs::create_dir(path)
-                    };
-                    if let Err(e) = result {
-                        eprintln!("mkdir: {dir_name}: {e}");
-                    }
+                        126
+                    });
                 }
-            }
+            };
+            rest = &rest[1..];
+            (
+                script.to_string(),
+                Input::Lines(Box::new(BufReader::new(file))),
+            )
         }
-        _ => eprintln!("{command}: command not found"),
-    }
+    };
+    shell.name = name;
+    shell.positional = rest.to_vec();
+    input
 }
 
-// Runs a pipeline of one or more stages, feeding each stage's captured
-// output to the next as `input`. The last stage writes to `redirect`'s
-// target file if present, otherwise to real stdout.
-fn run_pipeline(
-    stages: &[Vec<String>],
-    redirect: Option<&Redirect>,
-    previous_dir: &mut Option<String>,
-) {
-    let last_index = stages.len() - 1;
-    let mut piped_input = String::new();
-
-    for (i, stage) in stages.iter().enumerate() {
-        let command = stage[0].as_str();
-        let args: Vec<&str> = stage[1..].iter().map(String::as_str).collect();
+fn main() {
+    let mut shell = Shell::new();
+    let mut input = start(&mut shell);
+    let interactive = input.is_interactive();
+    shell.interactive = interactive;
+    if interactive {
+        external::handle_terminal_signals();
+        shell.history.load(&mut shell.vars);
+    }
 
-        if i != last_index {
-            let mut buffer: Vec<u8> = Vec::new();
-            execute_command(command, &args, &piped_input, &mut buffer, previous_dir);
-            piped_input = String::from_utf8_lossy(&buffer).into_owned();
+    loop {
+        if interactive {
+            run_prompt_command(&mut shell);
+        }
+        if let Some(status) = shell.exit_status {
+            exit(status);
+        }
+        // Like bash, a command cancelled with Ctrl-C, at any of its lines,
+        // leaves a status of 130.
+        let line = input.read_line(prompt::primary, shell.history.entries(), &shell);
+        let mut command = match line {
+            Entered::Line(command) => command,
+            Entered::Cancelled => {
+                shell.last_status = 130;
+                continue;
+            }
+            Entered::EndOfInput => {
+                if interactive {
+                    println!();
+                }
+                exit(shell.last_status); // Exit on Ctrl+D or at the end of input
+            }
+        };
+        if !read_continuation_lines(&mut command, &mut input, &shell) {
+            shell.last_status = 130;
             continue;
         }
 
-        match redirect {
-            None => {
-                let mut stdout = io::stdout();
-                execute_command(command, &args, &piped_input, &mut stdout, previous_dir);
-            }
-            Some(Redirect::Overwrite(filename)) => match File::create(filename) {
-                Ok(mut file) => {
-                    execute_command(command, &args, &piped_input, &mut file, previous_dir);
+        if interactive {
+            // Like bash, show the line that `!` expansion produced before
+            // running it.
+            match shell.history.expand(&command) {
+                Ok(Some(expanded)) => {
+                    println!("{expanded}");
+                    command = expanded;
                 }
-                Err(e) => eprintln!("{filename}: {e}"),
-            },
-            Some(Redirect::Append(filename)) => {
-                match OpenOptions::new().create(true).append(true).open(filename) {
-                    Ok(mut file) => {
-                        execute_command(command, &args, &piped_input, &mut file, previous_dir);
-                    }
-                    Err(e) => eprintln!("{filename}: {e}"),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("{e}");
+                    shell.last_status = 1;
+                    continue;
                 }
             }
+            shell.history.add(&command, &shell.vars);
         }
-    }
-}
-
-fn main() {
-    let mut previous_dir: Option<String> = None;
-
-    loop {
-        print!("$ ");
-        io::stdout().flush().unwrap();
 
-        let mut input = String::new();
-        let bytes_read = io::stdin().read_line(&mut input).unwrap_or(0);
-        if bytes_read == 0 {
-            println!();
-            exit(0); // Exit on Ctrl+D
-        }
+        // A syntax error ends a script, but not an interactive session.
+        let syntax_error = |e: String, shell: &mut Shell| {
+            eprintln!("{e}");
+            if !interactive {
+                exit(2);
+            }
+            shell.last_status = 2;
+        };
+        let tokens = match tokenize(command.trim()) {
+            Ok(tokens) if tokens.is_empty() => continue,
+            Ok(tokens) => tokens,
+            Err(e) => {
+                syntax_error(e, &mut shell);
+                continue;
+            }
+        };
 
-        let tokens = tokenize(input.trim());
-        if tokens.is_empty() {
-            continue;
+        match parse_list(&tokens) {
+            Ok(mut list) => {
+                if read_heredoc_bodies(&mut list, &mut input, &shell) {
+                    run_list(&list, &mut shell);
+                } else {
+                    shell.last_status = 130;
+                }
+            }
+            Err(e) => syntax_error(e, &mut shell),
         }
-        let tokens = glob::expand_all(&tokens);
 
-        match parse_pipeline(&tokens) {
-            Ok((stages, redirect)) => run_pipeline(&stages, redirect.as_ref(), &mut previous_dir),
-            Err(e) => eprintln!("{e}"),
+        if let Some(status) = shell.exit_status {
+            exit(status);
         }
     }
 }
@@ -1,3 +1,6 @@
+use crate::expand::remove_quotes;
+use crate::vars::split_assignment;
+
 pub fn parse_flags(args: &[&str]) -> Vec<String> {
     let mut parsed_flags = Vec::new();
 
@@ -14,95 +17,613 @@ pub fn parse_flags(args: &[&str]) -> Vec<String> {
     parsed_flags
 }
 
-// Splits a line into tokens, treating single- or double-quoted spans as a
-// single argument so that e.g. `mkdir "my dir"` produces one argument
-// containing a space instead of two.
-pub fn tokenize(input: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
+// Every operator `tokenize` recognises, longest first so e.g. `>>` isn't
+// read as two `>`s.
+const OPERATORS: &[&str] = &[
+    "<<<", "<<-", "&>>", "&&", "||", "<<", "<&", ">>", ">&", "&>", "|", "&", ";", "<", ">",
+];
+
+// The index of the `'` that closes a single-quoted span starting at
+// `start`. Only in a `$'...'` string (`escapes`) can a backslash escape it.
+fn single_quote_end(chars: &[char], start: usize, escapes: bool) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if escapes => i += 1,
+            '\'' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// The index of the `"` that closes a double-quoted span starting at
+// `start`, skipping escaped characters and any `${...}` or command
+// substitutions inside it (which may contain quotes of their own).
+pub fn double_quote_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '"' => return Some(i),
+            '$' if chars.get(i + 1) == Some(&'(') => i = substitution_end(chars, i + 2)?,
+            '$' if chars.get(i + 1) == Some(&'{') => i = matching_brace(chars, i + 2)?,
+            '`' => i = backtick_end(chars, i + 1)?,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// The index of the backtick that closes a `` `...` `` command substitution
+// whose contents start at `start`.
+pub fn backtick_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '`' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// The index of the `)` that closes a `$(...)` command substitution whose
+// contents start at `start`. Parentheses nest, and quoted or escaped ones
+// (and any inside a nested substitution) don't count.
+pub fn substitution_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => {
+                let ansi_c = i > 0 && chars[i - 1] == '$';
+                i = single_quote_end(chars, i + 1, ansi_c)?;
+            }
+            '"' => i = double_quote_end(chars, i + 1)?,
+            '`' => i = backtick_end(chars, i + 1)?,
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// The index of the `}` that closes a `${...}` whose contents start at
+// `start`. As in bash, a nested `${...}` is skipped whole but a plain `{`
+// doesn't need a `}` of its own, and quoted or escaped braces (or any
+// inside a command substitution) don't count.
+pub fn matching_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = single_quote_end(chars, i + 1, false)?,
+            '"' => i = double_quote_end(chars, i + 1)?,
+            '`' => i = backtick_end(chars, i + 1)?,
+            '$' if chars.get(i + 1) == Some(&'(') => i = substitution_end(chars, i + 2)?,
+            '$' if chars.get(i + 1) == Some(&'{') => i = matching_brace(chars, i + 2)?,
+            '}' => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Whether the `((` at `chars[start]` is closed by a matching `))`, making
+// it an arithmetic command.
+fn is_arithmetic_command(chars: &[char], start: usize) -> bool {
+    substitution_end(chars, start + 1).is_some_and(|end| end > start + 2 && chars[end - 1] == ')')
+}
+
+// Splits a line into words and operators. Words are returned exactly as
+// written, quotes and backslashes included, since which parts were quoted
+// matters later: for expansion, and for whether a here-document's body is
+// expanded. Quoting follows POSIX: a backslash outside quotes escapes the
+// next character, nothing is special inside `'...'`, `"..."` keeps `$` and
+// backslash escapes special, and bash's `$'...'` allows C-style escapes.
+// Operators (`|`, `&&`, `;`, `>`, `2>&1`, `<<` and so on) are recognised
+// with or without spaces around them, and a `${...}`, `$(...)` or
+// `` `...` `` is kept whole even if it contains spaces or operators, as is
+// a `((...))` arithmetic command. An unquoted newline separates commands
+// like `;`, except where a command can't end yet (at the start, or after
+// `|`, `&&` or `||`), where it's just a space. A `#` at the start of a
+// word comments out the rest of the line, which also covers a script's
+// `#!` line. Errors on a quote, command substitution or `${` that's never
+// closed.
+pub fn tokenize(input: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens: Vec<String> = Vec::new();
     let mut current = String::new();
-    let mut in_single_quotes = false;
-    let mut in_double_quotes = false;
     let mut has_token = false;
+    let mut brace_depth = 0;
+    let mut i = 0;
 
-    for c in input.chars() {
+    while i < chars.len() {
+        let c = chars[i];
         match c {
-            '\'' if !in_double_quotes => {
-                in_single_quotes = !in_single_quotes;
+            '\\' => {
+                current.push(c);
+                if let Some(&next) = chars.get(i + 1) {
+                    current.push(next);
+                    i += 1;
+                }
+                has_token = true;
+            }
+            '\'' | '"' | '`' => {
+                // `$'...'` is the only single quoting where a backslash can
+                // escape the closing quote.
+                let ansi_c = c == '\'' && current.ends_with('$') && !current.ends_with("\\$");
+                let end = match c {
+                    '\'' => single_quote_end(&chars, i + 1, ansi_c),
+                    '"' => double_quote_end(&chars, i + 1),
+                    _ => backtick_end(&chars, i + 1),
+                };
+                let Some(end) = end else {
+                    return Err(format!("syntax error: unterminated {c} quote"));
+                };
+                current.extend(&chars[i..=end]);
+                i = end;
                 has_token = true;
             }
-            '"' if !in_single_quotes => {
-                in_double_quotes = !in_double_quotes;
+            '(' if !has_token
+                && chars.get(i + 1) == Some(&'(')
+                && is_arithmetic_command(&chars, i) =>
+            {
+                let end = substitution_end(&chars, i + 1).unwrap();
+                current.extend(&chars[i..=end]);
                 has_token = true;
+                i = end;
             }
-            c if c.is_whitespace() && !in_single_quotes && !in_double_quotes => {
+            '(' if current.ends_with('$') && !current.ends_with("\\$") => {
+                let Some(end) = substitution_end(&chars, i + 1) else {
+                    return Err("syntax error: unterminated $( command substitution".to_string());
+                };
+                current.extend(&chars[i..=end]);
+                i = end;
+            }
+            '{' if current.ends_with('$') && !current.ends_with("\\$") => {
+                brace_depth += 1;
+                current.push(c);
+            }
+            '}' if brace_depth > 0 => {
+                brace_depth -= 1;
+                current.push(c);
+            }
+            c if brace_depth > 0 => current.push(c),
+            // A `#` that starts a word starts a comment, which runs to the
+            // end of the line.
+            '#' if !has_token => {
+                while chars.get(i + 1).is_some_and(|&c| c != '\n') {
+                    i += 1;
+                }
+            }
+            '\n' => {
                 if has_token {
                     tokens.push(std::mem::take(&mut current));
                     has_token = false;
                 }
+                let ends_command = tokens
+                    .last()
+                    .is_some_and(|last| !matches!(last.as_str(), ";" | "|" | "&&" | "||" | "&"));
+                if ends_command {
+                    tokens.push(";".to_string());
+                }
+            }
+            c if c.is_whitespace() => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            '|' | '&' | ';' | '<' | '>' => {
+                let rest: String = chars[i..].iter().collect();
+                let operator = OPERATORS.iter().find(|op| rest.starts_with(*op)).unwrap();
+                let mut token = String::new();
+                // A lone digit right before `<`/`>` is the descriptor the
+                // redirection applies to, as in `2>err`.
+                let is_fd = current.len() == 1 && current.chars().all(|d| d.is_ascii_digit());
+                if is_fd && (c == '<' || c == '>') {
+                    token = std::mem::take(&mut current);
+                } else if has_token {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                has_token = false;
+                token.push_str(operator);
+                i += operator.len();
+                // Keep the target of `>&`/`<&` attached (`2>&1`, `3<&-`),
+                // so it can't be mistaken for the descriptor of a following
+                // redirection.
+                if *operator == ">&" || *operator == "<&" {
+                    while let Some(&d) = chars.get(i).filter(|d| d.is_ascii_digit() || **d == '-') {
+                        token.push(d);
+                        i += 1;
+                    }
+                }
+                tokens.push(token);
+                continue;
             }
             c => {
                 current.push(c);
                 has_token = true;
             }
         }
+        i += 1;
     }
 
+    if brace_depth > 0 {
+        return Err("syntax error: unterminated ${ parameter expansion".to_string());
+    }
     if has_token {
         tokens.push(current);
     }
 
-    tokens
+    Ok(tokens)
 }
 
+// How a line that doesn't finish its command carries on into the next.
 #[derive(Debug, PartialEq)]
-pub enum Redirect {
+pub enum Continuation {
+    // It ends in an unquoted backslash, which goes, along with the
+    // newline, and the next line carries straight on.
+    Backslash,
+    // It ends with `|`, `&&` or `||`: the next line follows after a space.
+    Operator,
+    // A quote, `$(`, backtick or `${` is still open: the newline is part of
+    // it, and the next line follows it.
+    Newline,
+}
+
+// Whether `input` needs more lines before it can be run, and if so how
+// they join on.
+pub fn continuation(input: &str) -> Option<Continuation> {
+    let Ok(tokens) = tokenize(input) else {
+        return Some(Continuation::Newline);
+    };
+    let last = tokens.last()?;
+    if matches!(last.as_str(), "|" | "&&" | "||") {
+        return Some(Continuation::Operator);
+    }
+    // Only a backslash in the last word counts, not one at the end of a
+    // comment. A word can only end in an unpaired backslash when it's the
+    // last character of the input, since otherwise it would have taken the
+    // next character along with it.
+    let backslashes = last.chars().rev().take_while(|&c| c == '\\').count();
+    (backslashes % 2 == 1).then_some(Continuation::Backslash)
+}
+
+// A single redirection of file descriptor `fd`. Redirections are applied
+// in the order they were written, so `> out 2>&1` sends both stdout and
+// stderr to `out`, while `2>&1 > out` sends stderr to the original stdout.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum RedirectKind {
+    // `N> file`
     Overwrite(String),
+    // `N>> file`
     Append(String),
+    // `N< file`
+    Input(String),
+    // `<<< word`: the word plus a trailing newline.
+    HereString(String),
+    // `<< DELIM` / `<<- DELIM`, whose body comes from the lines after the
+    // command.
+    HereDoc(HereDoc),
+    // `N>&M` / `N<&M`: make N a copy of descriptor M.
+    Dup(i32),
+    // `N>&-` / `N<&-`
+    Close,
 }
 
-// Splits already-tokenized input into pipeline stages on `|`, and pulls a
-// trailing `>`/`>>` (with its filename) off the last stage into a Redirect.
-// Errors on an empty stage (e.g. a leading/trailing/doubled `|`) or a
-// redirection operator with no filename after it.
-pub fn parse_pipeline(tokens: &[String]) -> Result<(Vec<Vec<String>>, Option<Redirect>), String> {
-    let mut stages: Vec<Vec<String>> = vec![Vec::new()];
-    for token in tokens {
-        if token == "|" {
-            stages.push(Vec::new());
+#[derive(Debug, PartialEq, Clone)]
+pub struct HereDoc {
+    pub delimiter: String,
+    // `<<-`: leading tabs are stripped from every body line (and from the
+    // delimiter line), so the body can be indented along with the script.
+    pub strip_tabs: bool,
+    // False if any part of the delimiter was quoted, in which case the body
+    // is used literally rather than expanded.
+    pub expand: bool,
+    // Filled in by the REPL once it has read the lines up to the delimiter.
+    pub body: String,
+}
+
+impl HereDoc {
+    // Checks whether `line` (without its newline) ends the body, and if not,
+    // returns it as it should appear in the body.
+    pub fn body_line<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let line = if self.strip_tabs {
+            line.trim_start_matches('\t')
         } else {
-            stages.last_mut().unwrap().push(token.clone());
-        }
+            line
+        };
+        (line != self.delimiter).then_some(line)
     }
+}
+
+// One command of a pipeline: the `NAME=value` assignments in front of it,
+// its words (command name first) and its redirections, in the order they
+// were written. Redirections can appear anywhere among the words, so
+// `> out echo hi` and `echo > out hi` both write "hi" to `out`.
+#[derive(Debug, PartialEq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<(String, String)>,
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+// One pipeline of a command list: its `|`-separated commands.
+#[derive(Debug, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+}
 
-    if stages.iter().any(Vec::is_empty) {
-        return Err("syntax error: unexpected '|'".to_string());
+// Longest first, so e.g. `>>` isn't mistaken for `>` followed by junk.
+const REDIRECT_OPERATORS: &[&str] = &["<<<", "<<-", "&>>", "<<", ">>", ">&", "<&", "&>", ">", "<"];
+
+// Recognises a redirection operator token, splitting it into its optional
+// leading descriptor number, the operator itself, and anything attached
+// after a `>&`/`<&` (so `2>&1` gives `(Some(2), ">&", "1")`). Only a single
+// digit is accepted as a descriptor, as in POSIX sh.
+fn split_redirect_operator(token: &str) -> Option<(Option<i32>, &'static str, &str)> {
+    let (fd, rest) = match token.chars().next() {
+        Some(c @ '0'..='9') => (c.to_digit(10).map(|d| d as i32), &token[1..]),
+        _ => (None, token),
+    };
+    let operator = REDIRECT_OPERATORS.iter().find(|op| rest.starts_with(*op))?;
+    let attached = &rest[operator.len()..];
+    let allows_attached = *operator == ">&" || *operator == "<&";
+    if (fd.is_some() && operator.starts_with('&')) || (!allows_attached && !attached.is_empty()) {
+        return None;
     }
+    Some((fd, operator, attached))
+}
 
-    let last = stages.last_mut().unwrap();
-    let redirect = match last.iter().position(|t| t == ">" || t == ">>") {
-        None => None,
-        Some(pos) => {
-            if pos + 1 >= last.len() {
-                return Err("syntax error: expected filename after redirection".to_string());
-            }
-            let is_append = last[pos] == ">>";
-            let filename = last[pos + 1].clone();
-            last.truncate(pos);
-            Some(if is_append {
-                Redirect::Append(filename)
+fn is_redirect_operator(token: &str) -> bool {
+    split_redirect_operator(token).is_some()
+}
+
+// Builds the redirection(s) for one operator and its word. `&>` and `&>>`
+// are shorthand for two redirections: stdout to the file, then stderr to
+// wherever stdout now points.
+fn make_redirects(fd: Option<i32>, operator: &str, word: String) -> Result<Vec<Redirect>, String> {
+    let output = |kind| Redirect {
+        fd: fd.unwrap_or(1),
+        kind,
+    };
+    let both = |kind| {
+        vec![
+            Redirect { fd: 1, kind },
+            Redirect {
+                fd: 2,
+                kind: RedirectKind::Dup(1),
+            },
+        ]
+    };
+    Ok(match operator {
+        ">" => vec![output(RedirectKind::Overwrite(word))],
+        ">>" => vec![output(RedirectKind::Append(word))],
+        "&>" => both(RedirectKind::Overwrite(word)),
+        "&>>" => both(RedirectKind::Append(word)),
+        ">&" | "<&" => {
+            let default_fd = if operator == ">&" { 1 } else { 0 };
+            let kind = if word == "-" {
+                RedirectKind::Close
+            } else if let Ok(target) = word.parse::<i32>() {
+                RedirectKind::Dup(target)
+            } else if operator == ">&" && fd.is_none() {
+                // `>& file` is an older spelling of `&> file`.
+                return Ok(both(RedirectKind::Overwrite(word)));
             } else {
-                Redirect::Overwrite(filename)
-            })
+                return Err(format!("{word}: ambiguous redirect"));
+            };
+            vec![Redirect {
+                fd: fd.unwrap_or(default_fd),
+                kind,
+            }]
         }
+        _ => {
+            let kind = match operator {
+                "<" => RedirectKind::Input(word),
+                "<<<" => RedirectKind::HereString(word),
+                _ => {
+                    let expand = !word.contains(['\'', '"', '\\']);
+                    RedirectKind::HereDoc(HereDoc {
+                        delimiter: remove_quotes(&word),
+                        strip_tabs: operator == "<<-",
+                        expand,
+                        body: String::new(),
+                    })
+                }
+            };
+            vec![Redirect {
+                fd: fd.unwrap_or(0),
+                kind,
+            }]
+        }
+    })
+}
+
+// Parses the redirection starting at `tokens[pos]`, returning it along with
+// how many tokens it used up (one for a self-contained `2>&1`, otherwise
+// two for the operator and its word).
+fn parse_redirect(tokens: &[String], pos: usize) -> Result<(Vec<Redirect>, usize), String> {
+    let (fd, operator, attached) = split_redirect_operator(&tokens[pos]).unwrap();
+    if !attached.is_empty() {
+        return Ok((make_redirects(fd, operator, attached.to_string())?, 1));
+    }
+    let Some(word) = tokens.get(pos + 1) else {
+        return Err("syntax error: expected filename after redirection".to_string());
     };
+    Ok((make_redirects(fd, operator, word.clone())?, 2))
+}
+
+// Splits already-tokenized input into pipeline stages on `|`, separating
+// each stage's redirections (`<`, `>`, `>>`, `2>&1`, `<<<`, `<<` and so on,
+// each with its word where it takes one) and leading `NAME=value`
+// assignments from its ordinary words. Errors
+// on an empty stage (e.g. a leading/trailing/doubled `|`) or a redirection
+// operator with no word after it.
+pub fn parse_pipeline(tokens: &[String]) -> Result<Pipeline, String> {
+    let mut commands = Vec::new();
+    for stage in tokens.split(|token| token == "|") {
+        if stage.is_empty() {
+            return Err("syntax error: unexpected '|'".to_string());
+        }
+
+        let mut command = SimpleCommand {
+            assignments: Vec::new(),
+            words: Vec::new(),
+            redirects: Vec::new(),
+        };
+        let mut pos = 0;
+        while pos < stage.len() {
+            if is_redirect_operator(&stage[pos]) {
+                let (redirects, used) = parse_redirect(stage, pos)?;
+                command.redirects.extend(redirects);
+                pos += used;
+            } else if let Some((name, value)) =
+                split_assignment(&stage[pos]).filter(|_| command.words.is_empty())
+            {
+                command
+                    .assignments
+                    .push((name.to_string(), value.to_string()));
+                pos += 1;
+            } else {
+                command.words.push(stage[pos].clone());
+                pos += 1;
+            }
+        }
+        commands.push(command);
+    }
+
+    Ok(Pipeline { commands })
+}
+
+// All the here-documents in `list`, in the order their bodies appear in the
+// input, for the REPL to fill in.
+pub fn heredocs_mut(list: &mut [AndOrList]) -> Vec<&mut HereDoc> {
+    list.iter_mut()
+        .flat_map(|and_or| {
+            std::iter::once(&mut and_or.first).chain(and_or.rest.iter_mut().map(|(_, p)| p))
+        })
+        .flat_map(|pipeline| pipeline.commands.iter_mut())
+        .flat_map(|command| command.redirects.iter_mut())
+        .filter_map(|redirect| match &mut redirect.kind {
+            RedirectKind::HereDoc(heredoc) => Some(heredoc),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+// A chain of pipelines joined by `&&`/`||`, e.g. `make && make test || echo
+// failed`. Each entry in `rest` only runs depending on the exit status of
+// everything before it.
+#[derive(Debug, PartialEq)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+fn unexpected(token: &str) -> String {
+    format!("syntax error: unexpected '{token}'")
+}
+
+// Closes off the pipeline in `current`, attaching it to the and-or list
+// being built with whatever connector preceded it. `next` is the token that
+// ended it, for the error message if the pipeline turns out to be empty.
+fn finish_pipeline(
+    current: &mut Vec<String>,
+    and_or: &mut Option<AndOrList>,
+    pending: Option<Connector>,
+    next: &str,
+) -> Result<(), String> {
+    if current.is_empty() {
+        return Err(unexpected(next));
+    }
+    let pipeline = parse_pipeline(&std::mem::take(current))?;
+    match (and_or.as_mut(), pending) {
+        (Some(and_or), Some(connector)) => and_or.rest.push((connector, pipeline)),
+        _ => {
+            *and_or = Some(AndOrList {
+                first: pipeline,
+                rest: Vec::new(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Parses a full command line into its `;`-separated and-or lists. A
+// trailing `;` is allowed, but any other empty command (a leading `;`, a
+// doubled `;;`, or `&&`/`||` with nothing on one side) is a syntax error.
+pub fn parse_list(tokens: &[String]) -> Result<Vec<AndOrList>, String> {
+    let mut list = Vec::new();
+    let mut and_or: Option<AndOrList> = None;
+    let mut pending: Option<Connector> = None;
+    let mut current: Vec<String> = Vec::new();
+
+    for token in tokens {
+        match token.as_str() {
+            "&&" | "||" => {
+                finish_pipeline(&mut current, &mut and_or, pending, token)?;
+                pending = Some(if token == "&&" {
+                    Connector::And
+                } else {
+                    Connector::Or
+                });
+            }
+            "&" => return Err("syntax error: background jobs ('&') are not supported".to_string()),
+            ";" => {
+                finish_pipeline(&mut current, &mut and_or, pending, token)?;
+                list.extend(and_or.take());
+                pending = None;
+            }
+            _ => current.push(token.clone()),
+        }
+    }
+
+    if !current.is_empty() {
+        finish_pipeline(&mut current, &mut and_or, pending, "")?;
+    } else if let Some(connector) = pending {
+        return Err(unexpected(match connector {
+            Connector::And => "&&",
+            Connector::Or => "||",
+        }));
+    }
+    list.extend(and_or);
 
-    Ok((stages, redirect))
+    Ok(list)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn redirect(fd: i32, kind: RedirectKind) -> Redirect {
+        Redirect { fd, kind }
+    }
+
     fn as_str_vec(v: &[String]) -> Vec<&str> {
         v.iter().map(String::as_str).collect()
     }
@@ -130,74 +651,235 @@ mod tests {
         );
     }
 
+    fn lex(input: &str) -> Vec<String> {
+        tokenize(input).unwrap()
+    }
+
     #[test]
     fn tokenize_splits_on_whitespace() {
-        assert_eq!(tokenize("ls -la /tmp"), vec!["ls", "-la", "/tmp"]);
+        assert_eq!(lex("ls -la /tmp"), vec!["ls", "-la", "/tmp"]);
     }
 
     #[test]
-    fn tokenize_keeps_double_quoted_span_as_one_token() {
-        assert_eq!(tokenize(r#"mkdir "my dir""#), vec!["mkdir", "my dir"]);
+    fn tokenize_keeps_quoted_spans_in_one_word() {
+        assert_eq!(lex(r#"mkdir "my dir""#), vec!["mkdir", r#""my dir""#]);
+        assert_eq!(lex("mkdir 'my dir'"), vec!["mkdir", "'my dir'"]);
+        assert_eq!(
+            lex(r#"echo hello"a   b"'c'"#),
+            vec!["echo", r#"hello"a   b"'c'"#]
+        );
     }
 
     #[test]
-    fn tokenize_keeps_single_quoted_span_as_one_token() {
-        assert_eq!(tokenize("mkdir 'my dir'"), vec!["mkdir", "my dir"]);
+    fn tokenize_keeps_escaped_characters_in_the_word() {
+        assert_eq!(lex(r"echo a\ b \| \;"), vec!["echo", r"a\ b", r"\|", r"\;"]);
+        assert_eq!(lex(r#"echo "say \"hi\"""#), vec!["echo", r#""say \"hi\"""#]);
+        assert_eq!(lex(r"echo 'a\' b"), vec!["echo", r"'a\'", "b"]);
+        assert_eq!(lex(r"echo $'it\'s' x"), vec!["echo", r"$'it\'s'", "x"]);
     }
 
     #[test]
-    fn tokenize_preserves_internal_whitespace_in_quotes() {
-        assert_eq!(tokenize(r#"echo "a   b""#), vec!["echo", "a   b"]);
+    fn tokenize_lets_quotes_inside_a_quoted_parameter_expansion() {
+        assert_eq!(
+            lex(r#"echo "${x:-"q r"}" "${x:+"}"}" x"#),
+            vec!["echo", r#""${x:-"q r"}""#, r#""${x:+"}"}""#, "x"]
+        );
     }
 
     #[test]
-    fn tokenize_concatenates_adjacent_quoted_and_unquoted_text() {
-        assert_eq!(tokenize(r#"echo hello"world""#), vec!["echo", "helloworld"]);
+    fn tokenize_rejects_unterminated_quotes() {
+        assert!(tokenize("echo 'oops").is_err());
+        assert!(tokenize(r#"echo "oops\""#).is_err());
+        assert!(tokenize(r"echo $'oops\'").is_err());
+    }
+
+    #[test]
+    fn tokenize_keeps_command_substitutions_whole() {
+        assert_eq!(
+            lex("cd $(pwd)/sub; ls"),
+            vec!["cd", "$(pwd)/sub", ";", "ls"]
+        );
+        assert_eq!(
+            lex("echo $(ls | sort -r; echo ')') x"),
+            vec!["echo", "$(ls | sort -r; echo ')')", "x"]
+        );
+        assert_eq!(
+            lex(r#"echo "at $(date "+%H %M")" `echo a|b`"#),
+            vec!["echo", r#""at $(date "+%H %M")""#, "`echo a|b`"]
+        );
+        assert_eq!(
+            lex("echo $(echo $(echo (x)))"),
+            vec!["echo", "$(echo $(echo (x)))"]
+        );
+        assert_eq!(lex(r"echo \$(ls)"), vec!["echo", r"\$(ls)"]);
+    }
+
+    #[test]
+    fn tokenize_keeps_arithmetic_commands_whole() {
+        assert_eq!(
+            lex("((i < 3 && j > 1)) && echo y"),
+            vec!["((i < 3 && j > 1))", "&&", "echo", "y"]
+        );
+        assert_eq!(
+            lex("echo $((2 * (3 + 4)))"),
+            vec!["echo", "$((2 * (3 + 4)))"]
+        );
+        assert_eq!(lex("((a)"), vec!["((a)"]);
+    }
+
+    #[test]
+    fn tokenize_rejects_unterminated_substitutions() {
+        assert!(tokenize("echo $(ls").is_err());
+        assert!(tokenize("echo $(echo ')'").is_err());
+        assert!(tokenize("echo `ls").is_err());
+    }
+
+    #[test]
+    fn tokenize_treats_newlines_as_separators() {
+        assert_eq!(
+            lex("echo a\necho b\n"),
+            vec!["echo", "a", ";", "echo", "b", ";"]
+        );
+        assert_eq!(
+            lex("\nls |\n  wc &&\n\ntrue"),
+            vec!["ls", "|", "wc", "&&", "true"]
+        );
+        assert_eq!(lex("echo 'a\nb'"), vec!["echo", "'a\nb'"]);
+        assert_eq!(lex("x=$(\necho a\n)"), vec!["x=$(\necho a\n)"]);
+        assert!(tokenize("echo ${HOME").is_err());
+    }
+
+    #[test]
+    fn tokenize_skips_comments() {
+        assert_eq!(lex("echo hi # it's fine"), vec!["echo", "hi"]);
+        assert_eq!(
+            lex("#!/usr/bin/env zero_shell\necho a#b $# '#'\n"),
+            vec!["echo", "a#b", "$#", "'#'", ";"]
+        );
+        assert_eq!(lex("ls;# done"), vec!["ls", ";"]);
+        assert_eq!(lex("# only a comment"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn continuation_spots_unfinished_commands() {
+        assert_eq!(continuation("echo 'it"), Some(Continuation::Newline));
+        assert_eq!(continuation("echo \"$(ls"), Some(Continuation::Newline));
+        assert_eq!(continuation("ls |"), Some(Continuation::Operator));
+        assert_eq!(continuation("true &&"), Some(Continuation::Operator));
+        assert_eq!(continuation("echo a \\"), Some(Continuation::Backslash));
+        assert_eq!(continuation("echo a \\\\"), None);
+        assert_eq!(continuation("echo 'a \\'"), None);
+        assert_eq!(continuation("echo one # trailing \\"), None);
+        assert_eq!(continuation("echo a\n# note \\"), None);
+        assert_eq!(continuation("echo a;\\"), Some(Continuation::Backslash));
+        assert_eq!(continuation("echo a;"), None);
+        assert_eq!(continuation(""), None);
+    }
+
+    #[test]
+    fn tokenize_splits_operators_without_spaces() {
+        assert_eq!(lex("cd build;ls"), vec!["cd", "build", ";", "ls"]);
+        assert_eq!(lex("ls|cat"), vec!["ls", "|", "cat"]);
+        assert_eq!(lex("echo hi>f"), vec!["echo", "hi", ">", "f"]);
+        assert_eq!(lex("a&&b||c"), vec!["a", "&&", "b", "||", "c"]);
+        assert_eq!(lex("cat<<EOF"), vec!["cat", "<<", "EOF"]);
+        assert_eq!(lex("cat <<<word"), vec!["cat", "<<<", "word"]);
+        assert_eq!(lex("echo 'a;b' \"|\""), vec!["echo", "'a;b'", "\"|\""]);
+    }
+
+    #[test]
+    fn tokenize_attaches_descriptors_to_redirections() {
+        assert_eq!(lex("ls 2>err"), vec!["ls", "2>", "err"]);
+        assert_eq!(lex("ls x2>err"), vec!["ls", "x2", ">", "err"]);
+        assert_eq!(lex("ls 2>&1>f"), vec!["ls", "2>&1", ">", "f"]);
+        assert_eq!(lex("cmd 3<&-"), vec!["cmd", "3<&-"]);
+        assert_eq!(lex("cmd &>all"), vec!["cmd", "&>", "all"]);
+    }
+
+    #[test]
+    fn tokenize_keeps_parameter_expansions_whole() {
+        assert_eq!(
+            lex("echo ${X:-two words}; ls"),
+            vec!["echo", "${X:-two words}", ";", "ls"]
+        );
+        assert_eq!(lex("echo {a b}"), vec!["echo", "{a", "b}"]);
+        assert_eq!(lex(r"echo \${a b}"), vec!["echo", r"\${a", "b}"]);
     }
 
     #[test]
     fn tokenize_handles_empty_input() {
-        assert_eq!(tokenize(""), Vec::<String>::new());
+        assert_eq!(lex(""), Vec::<String>::new());
     }
 
     fn tokens(strs: &[&str]) -> Vec<String> {
         strs.iter().map(ToString::to_string).collect()
     }
 
+    fn stage_words(pipeline: &Pipeline) -> Vec<Vec<String>> {
+        pipeline.commands.iter().map(|c| c.words.clone()).collect()
+    }
+
+    fn first_redirect(pipeline: &Pipeline) -> Option<Redirect> {
+        pipeline.commands[0].redirects.first().cloned()
+    }
+
+    fn last_redirects(pipeline: &Pipeline) -> Vec<Redirect> {
+        pipeline.commands.last().unwrap().redirects.clone()
+    }
+
     #[test]
     fn parse_pipeline_single_command_has_no_redirect() {
-        let (stages, redirect) = parse_pipeline(&tokens(&["ls", "-la"])).unwrap();
+        let pipeline = parse_pipeline(&tokens(&["ls", "-la"])).unwrap();
+        let stages = stage_words(&pipeline);
+        let redirects = last_redirects(&pipeline);
         assert_eq!(stages, vec![tokens(&["ls", "-la"])]);
-        assert_eq!(redirect, None);
+        assert!(redirects.is_empty());
     }
 
     #[test]
     fn parse_pipeline_splits_stages_on_pipe() {
-        let (stages, redirect) = parse_pipeline(&tokens(&["ls", "|", "cat"])).unwrap();
+        let pipeline = parse_pipeline(&tokens(&["ls", "|", "cat"])).unwrap();
+        let stages = stage_words(&pipeline);
+        let redirects = last_redirects(&pipeline);
         assert_eq!(stages, vec![tokens(&["ls"]), tokens(&["cat"])]);
-        assert_eq!(redirect, None);
+        assert!(redirects.is_empty());
     }
 
     #[test]
     fn parse_pipeline_detects_overwrite_redirect() {
-        let (stages, redirect) = parse_pipeline(&tokens(&["echo", "hi", ">", "out.txt"])).unwrap();
+        let pipeline = parse_pipeline(&tokens(&["echo", "hi", ">", "out.txt"])).unwrap();
+        let stages = stage_words(&pipeline);
+        let redirects = last_redirects(&pipeline);
         assert_eq!(stages, vec![tokens(&["echo", "hi"])]);
-        assert_eq!(redirect, Some(Redirect::Overwrite("out.txt".to_string())));
+        assert_eq!(
+            redirects,
+            vec![redirect(1, RedirectKind::Overwrite("out.txt".to_string()))]
+        );
     }
 
     #[test]
     fn parse_pipeline_detects_append_redirect() {
-        let (stages, redirect) = parse_pipeline(&tokens(&["echo", "hi", ">>", "out.txt"])).unwrap();
+        let pipeline = parse_pipeline(&tokens(&["echo", "hi", ">>", "out.txt"])).unwrap();
+        let stages = stage_words(&pipeline);
+        let redirects = last_redirects(&pipeline);
         assert_eq!(stages, vec![tokens(&["echo", "hi"])]);
-        assert_eq!(redirect, Some(Redirect::Append("out.txt".to_string())));
+        assert_eq!(
+            redirects,
+            vec![redirect(1, RedirectKind::Append("out.txt".to_string()))]
+        );
     }
 
     #[test]
-    fn parse_pipeline_redirect_only_applies_to_last_stage() {
-        let (stages, redirect) =
-            parse_pipeline(&tokens(&["ls", "|", "cat", ">", "out.txt"])).unwrap();
+    fn parse_pipeline_redirect_applies_to_its_own_stage() {
+        let pipeline = parse_pipeline(&tokens(&["ls", "|", "cat", ">", "out.txt"])).unwrap();
+        let stages = stage_words(&pipeline);
+        let redirects = last_redirects(&pipeline);
         assert_eq!(stages, vec![tokens(&["ls"]), tokens(&["cat"])]);
-        assert_eq!(redirect, Some(Redirect::Overwrite("out.txt".to_string())));
+        assert!(pipeline.commands[0].redirects.is_empty());
+        assert_eq!(
+            redirects,
+            vec![redirect(1, RedirectKind::Overwrite("out.txt".to_string()))]
+        );
     }
 
     #[test]
@@ -211,4 +893,338 @@ mod tests {
     fn parse_pipeline_errors_on_redirect_without_filename() {
         assert!(parse_pipeline(&tokens(&["echo", "hi", ">"])).is_err());
     }
+
+    fn pipeline(strs: &[&str]) -> Pipeline {
+        Pipeline {
+            commands: vec![SimpleCommand {
+                assignments: vec![],
+                words: tokens(strs),
+                redirects: vec![],
+            }],
+        }
+    }
+
+    #[test]
+    fn parse_list_splits_on_semicolons() {
+        let list = parse_list(&tokens(&["cd", "build", ";", "ls"])).unwrap();
+        assert_eq!(
+            list,
+            vec![
+                AndOrList {
+                    first: pipeline(&["cd", "build"]),
+                    rest: vec![],
+                },
+                AndOrList {
+                    first: pipeline(&["ls"]),
+                    rest: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_list_chains_and_or_connectors() {
+        let list = parse_list(&tokens(&["make", "&&", "ls", "||", "echo", "failed"])).unwrap();
+        assert_eq!(
+            list,
+            vec![AndOrList {
+                first: pipeline(&["make"]),
+                rest: vec![
+                    (Connector::And, pipeline(&["ls"])),
+                    (Connector::Or, pipeline(&["echo", "failed"])),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_list_keeps_pipes_and_redirects_within_a_pipeline() {
+        let list =
+            parse_list(&tokens(&["ls", "|", "cat", ">", "out", "&&", "cat", "out"])).unwrap();
+        assert_eq!(
+            list[0].first,
+            Pipeline {
+                commands: vec![
+                    SimpleCommand {
+                        assignments: vec![],
+                        words: tokens(&["ls"]),
+                        redirects: vec![],
+                    },
+                    SimpleCommand {
+                        assignments: vec![],
+                        words: tokens(&["cat"]),
+                        redirects: vec![redirect(1, RedirectKind::Overwrite("out".to_string()))],
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            list[0].rest,
+            vec![(Connector::And, pipeline(&["cat", "out"]))]
+        );
+    }
+
+    #[test]
+    fn parse_list_allows_trailing_semicolon() {
+        let list = parse_list(&tokens(&["ls", ";"])).unwrap();
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn parse_list_errors_on_empty_commands() {
+        assert!(parse_list(&tokens(&[";", "ls"])).is_err());
+        assert!(parse_list(&tokens(&["ls", ";", ";"])).is_err());
+        assert!(parse_list(&tokens(&["&&", "ls"])).is_err());
+        assert!(parse_list(&tokens(&["ls", "&&"])).is_err());
+        assert!(parse_list(&tokens(&["ls", "||", ";", "ls"])).is_err());
+        assert!(parse_list(&tokens(&["sleep", "1", "&"])).is_err());
+    }
+
+    #[test]
+    fn parse_pipeline_detects_input_redirect_on_first_stage() {
+        let pipeline = parse_pipeline(&tokens(&["cat", "<", "in.txt", "|", "cat"])).unwrap();
+        assert_eq!(
+            stage_words(&pipeline),
+            vec![tokens(&["cat"]), tokens(&["cat"])]
+        );
+        assert_eq!(
+            first_redirect(&pipeline),
+            Some(redirect(0, RedirectKind::Input("in.txt".to_string())))
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_handles_input_and_output_redirects_together() {
+        let pipeline = parse_pipeline(&tokens(&["cat", "<", "in", ">", "out"])).unwrap();
+        assert_eq!(stage_words(&pipeline), vec![tokens(&["cat"])]);
+        assert_eq!(
+            first_redirect(&pipeline),
+            Some(redirect(0, RedirectKind::Input("in".to_string())))
+        );
+        assert_eq!(
+            last_redirects(&pipeline),
+            vec![
+                redirect(0, RedirectKind::Input("in".to_string())),
+                redirect(1, RedirectKind::Overwrite("out".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_detects_here_string() {
+        let pipeline = parse_pipeline(&tokens(&["cat", "<<<", "hello"])).unwrap();
+        assert_eq!(
+            first_redirect(&pipeline),
+            Some(redirect(0, RedirectKind::HereString("hello".to_string())))
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_detects_heredoc_options() {
+        let pipeline = parse_pipeline(&tokens(&["cat", "<<-", "'EOF'"])).unwrap();
+        assert_eq!(
+            first_redirect(&pipeline),
+            Some(redirect(
+                0,
+                RedirectKind::HereDoc(HereDoc {
+                    delimiter: "EOF".to_string(),
+                    strip_tabs: true,
+                    expand: false,
+                    body: String::new(),
+                })
+            ))
+        );
+
+        let pipeline = parse_pipeline(&tokens(&["cat", "<<", "EOF"])).unwrap();
+        let Some(Redirect {
+            kind: RedirectKind::HereDoc(heredoc),
+            ..
+        }) = first_redirect(&pipeline)
+        else {
+            panic!("expected a here-document");
+        };
+        assert!(heredoc.expand);
+        assert!(!heredoc.strip_tabs);
+    }
+
+    #[test]
+    fn parse_pipeline_errors_on_input_redirect_without_word() {
+        assert!(parse_pipeline(&tokens(&["cat", "<"])).is_err());
+        assert!(parse_pipeline(&tokens(&["cat", "<<"])).is_err());
+    }
+
+    #[test]
+    fn heredoc_body_line_stops_at_delimiter() {
+        let heredoc = HereDoc {
+            delimiter: "EOF".to_string(),
+            strip_tabs: false,
+            expand: true,
+            body: String::new(),
+        };
+        assert_eq!(heredoc.body_line("\thello"), Some("\thello"));
+        assert_eq!(heredoc.body_line("EOF"), None);
+        assert_eq!(heredoc.body_line("\tEOF"), Some("\tEOF"));
+    }
+
+    #[test]
+    fn heredoc_body_line_strips_tabs_for_dash_form() {
+        let heredoc = HereDoc {
+            delimiter: "EOF".to_string(),
+            strip_tabs: true,
+            expand: true,
+            body: String::new(),
+        };
+        assert_eq!(heredoc.body_line("\t\thello"), Some("hello"));
+        assert_eq!(heredoc.body_line("\tEOF"), None);
+    }
+
+    #[test]
+    fn heredocs_mut_finds_heredocs_in_order() {
+        let mut list = parse_list(&tokens(&[
+            "cat", "<<", "A", ";", "echo", "&&", "cat", "<<", "B",
+        ]))
+        .unwrap();
+        let delimiters: Vec<String> = heredocs_mut(&mut list)
+            .into_iter()
+            .map(|h| h.delimiter.clone())
+            .collect();
+        assert_eq!(delimiters, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn parse_pipeline_collects_redirects_in_order() {
+        let pipeline =
+            parse_pipeline(&tokens(&["cmd", ">", "out", "2>&1", "3<", "in", "4>&-"])).unwrap();
+        assert_eq!(stage_words(&pipeline), vec![tokens(&["cmd"])]);
+        assert_eq!(
+            last_redirects(&pipeline),
+            vec![
+                redirect(1, RedirectKind::Overwrite("out".to_string())),
+                redirect(2, RedirectKind::Dup(1)),
+                redirect(3, RedirectKind::Input("in".to_string())),
+                redirect(4, RedirectKind::Close),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_handles_numbered_file_redirects() {
+        let pipeline = parse_pipeline(&tokens(&["cmd", "2>", "err", "2>>", "log"])).unwrap();
+        assert_eq!(
+            last_redirects(&pipeline),
+            vec![
+                redirect(2, RedirectKind::Overwrite("err".to_string())),
+                redirect(2, RedirectKind::Append("log".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_expands_ampersand_redirects_to_both_streams() {
+        for (operator, kind) in [
+            ("&>", RedirectKind::Overwrite("all".to_string())),
+            ("&>>", RedirectKind::Append("all".to_string())),
+            (">&", RedirectKind::Overwrite("all".to_string())),
+        ] {
+            let pipeline = parse_pipeline(&tokens(&["cmd", operator, "all"])).unwrap();
+            assert_eq!(
+                last_redirects(&pipeline),
+                vec![redirect(1, kind), redirect(2, RedirectKind::Dup(1))]
+            );
+        }
+    }
+
+    #[test]
+    fn parse_pipeline_handles_dup_with_separate_word() {
+        let pipeline = parse_pipeline(&tokens(&["cmd", ">&", "2"])).unwrap();
+        assert_eq!(
+            last_redirects(&pipeline),
+            vec![redirect(1, RedirectKind::Dup(2))]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_rejects_ambiguous_dup_target() {
+        assert!(parse_pipeline(&tokens(&["cmd", "2>&", "file"])).is_err());
+    }
+
+    #[test]
+    fn split_redirect_operator_ignores_ordinary_words() {
+        assert_eq!(split_redirect_operator("file"), None);
+        assert_eq!(split_redirect_operator("22>"), None);
+        assert_eq!(split_redirect_operator("2&>"), None);
+        assert_eq!(split_redirect_operator(">file"), None);
+        assert_eq!(split_redirect_operator("2>&1"), Some((Some(2), ">&", "1")));
+    }
+
+    #[test]
+    fn parse_pipeline_accepts_redirects_anywhere_among_words() {
+        let pipeline = parse_pipeline(&tokens(&["echo", "a", ">", "f", "b"])).unwrap();
+        assert_eq!(stage_words(&pipeline), vec![tokens(&["echo", "a", "b"])]);
+        assert_eq!(
+            last_redirects(&pipeline),
+            vec![redirect(1, RedirectKind::Overwrite("f".to_string()))]
+        );
+
+        let pipeline = parse_pipeline(&tokens(&["<", "in", "sort"])).unwrap();
+        assert_eq!(stage_words(&pipeline), vec![tokens(&["sort"])]);
+        assert_eq!(
+            first_redirect(&pipeline),
+            Some(redirect(0, RedirectKind::Input("in".to_string())))
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_accepts_redirects_on_every_stage() {
+        let pipeline = parse_pipeline(&tokens(&[
+            "ls", "missing", "2>&1", "|", "sort", "2>", "err", "|", "cat", ">", "out",
+        ]))
+        .unwrap();
+        assert_eq!(
+            pipeline.commands[0].redirects,
+            vec![redirect(2, RedirectKind::Dup(1))]
+        );
+        assert_eq!(
+            pipeline.commands[1].redirects,
+            vec![redirect(2, RedirectKind::Overwrite("err".to_string()))]
+        );
+        assert_eq!(
+            pipeline.commands[2].redirects,
+            vec![redirect(1, RedirectKind::Overwrite("out".to_string()))]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_allows_redirect_only_command() {
+        let pipeline = parse_pipeline(&tokens(&[">", "empty.txt"])).unwrap();
+        assert!(pipeline.commands[0].words.is_empty());
+        assert_eq!(
+            last_redirects(&pipeline),
+            vec![redirect(
+                1,
+                RedirectKind::Overwrite("empty.txt".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_separates_leading_assignments() {
+        let pipeline = parse_pipeline(&tokens(&["A=1", "B=", "env", "C=3"])).unwrap();
+        let command = &pipeline.commands[0];
+        assert_eq!(
+            command.assignments,
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), String::new())
+            ]
+        );
+        assert_eq!(command.words, tokens(&["env", "C=3"]));
+
+        let pipeline = parse_pipeline(&tokens(&["X=1"])).unwrap();
+        assert!(pipeline.commands[0].words.is_empty());
+        assert_eq!(pipeline.commands[0].assignments.len(), 1);
+
+        let pipeline = parse_pipeline(&tokens(&["1X=1", "--opt=2"])).unwrap();
+        assert!(pipeline.commands[0].assignments.is_empty());
+    }
 }
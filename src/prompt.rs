@@ -0,0 +1,188 @@
+use crate::exec::Shell;
+use crate::users::get_user_name_by_uid;
+use chrono::Local;
+use std::env;
+
+// What `\[` and `\]` turn into: marks around text that takes up no room
+// on screen (colour escape sequences, say), so the line editor can leave
+// it out when working out where the cursor is. Readline uses the same
+// two bytes.
+pub const IGNORE_START: char = '\x01';
+pub const IGNORE_END: char = '\x02';
+
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// The current directory ($PWD, as `cd` keeps it), with $HOME at its
+// start shortened to `~`.
+fn working_dir(shell: &Shell) -> String {
+    let cwd = match shell.vars.get("PWD") {
+        Some(pwd) => pwd.to_string(),
+        None => env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    match shell.vars.get("HOME").filter(|home| !home.is_empty()) {
+        Some(home) if cwd == home || cwd.starts_with(&format!("{home}/")) => {
+            format!("~{}", &cwd[home.len()..])
+        }
+        _ => cwd,
+    }
+}
+
+// Expands the backslash escapes in a prompt string, as bash does:
+//
+//   \u        the user name
+//   \h, \H    the host name, up to the first `.` or in full
+//   \w, \W    the current directory, or just its last part (`~` for $HOME)
+//   \$        `#` for root, `$` for anyone else
+//   \t, \T    the time as 24-hour or 12-hour HH:MM:SS
+//   \A, \@    the time as 24-hour HH:MM, or 12-hour with am/pm
+//   \d        the date, as in "Tue May 26"
+//   \?        the exit status of the last command
+//   \n, \e, \a  a newline, an escape character, a bell
+//   \nnn      the character with octal code nnn
+//   \[, \]    start and end a run of non-printing characters
+//   \\        a backslash
+//
+// Any other backslash is left as it is.
+pub fn render(template: &str, shell: &Shell) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let time = |format: &str| Local::now().format(format).to_string();
+        match chars.next() {
+            Some('u') => {
+                let uid = unsafe { libc::getuid() };
+                out.push_str(&get_user_name_by_uid(uid).unwrap_or_default());
+            }
+            Some('h') => out.push_str(host_name().split('.').next().unwrap_or("")),
+            Some('H') => out.push_str(&host_name()),
+            Some('w') => out.push_str(&working_dir(shell)),
+            Some('W') => {
+                let dir = working_dir(shell);
+                match dir.rsplit_once('/') {
+                    Some((_, last)) if !last.is_empty() => out.push_str(last),
+                    _ => out.push_str(&dir),
+                }
+            }
+            Some('$') => {
+                let root = unsafe { libc::geteuid() } == 0;
+                out.push(if root { '#' } else { '$' });
+            }
+            Some('t') => out.push_str(&time("%H:%M:%S")),
+            Some('T') => out.push_str(&time("%I:%M:%S")),
+            Some('A') => out.push_str(&time("%H:%M")),
+            Some('@') => out.push_str(&time("%I:%M %p")),
+            Some('d') => out.push_str(&time("%a %b %d")),
+            Some('?') => out.push_str(&shell.last_status.to_string()),
+            Some('n') => out.push('\n'),
+            Some('e') => out.push('\x1b'),
+            Some('a') => out.push('\x07'),
+            Some('[') => out.push(IGNORE_START),
+            Some(']') => out.push(IGNORE_END),
+            Some('\\') => out.push('\\'),
+            Some(first @ '0'..='7') => {
+                let mut code = first.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.extend(char::from_u32(code));
+            }
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+// The prompt for a new command, from $PS1.
+pub fn primary(shell: &Shell) -> String {
+    render(shell.vars.get("PS1").unwrap_or("$ "), shell)
+}
+
+// The prompt for each further line a command needs, from $PS2.
+pub fn secondary(shell: &Shell) -> String {
+    render(shell.vars.get("PS2").unwrap_or("> "), shell)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell_with(vars: &[(&str, &str)]) -> Shell {
+        let mut shell = Shell::default();
+        for (name, value) in vars {
+            shell.vars.set(name, value).unwrap();
+        }
+        shell
+    }
+
+    #[test]
+    fn render_shortens_the_home_directory() {
+        let shell = shell_with(&[("HOME", "/home/me"), ("PWD", "/home/me/src/app")]);
+        assert_eq!(render("[\\w] \\W", &shell), "[~/src/app] app");
+        let shell = shell_with(&[("HOME", "/home/me"), ("PWD", "/home/me")]);
+        assert_eq!(render("\\w \\W", &shell), "~ ~");
+        let shell = shell_with(&[("HOME", "/home/me"), ("PWD", "/home/meg")]);
+        assert_eq!(render("\\w", &shell), "/home/meg");
+        let shell = shell_with(&[("PWD", "/")]);
+        assert_eq!(render("\\W", &shell), "/");
+    }
+
+    #[test]
+    fn render_marks_non_printing_text() {
+        let shell = Shell::default();
+        assert_eq!(
+            render("\\[\\e[32m\\]ok\\[\\033[0m\\]", &shell),
+            "\x01\x1b[32m\x02ok\x01\x1b[0m\x02"
+        );
+    }
+
+    #[test]
+    fn render_fills_in_the_status_user_and_root_marker() {
+        let shell = Shell {
+            last_status: 127,
+            ..Shell::default()
+        };
+        assert_eq!(render("\\? \\\\ \\q \\", &shell), "127 \\ \\q \\");
+
+        let uid = unsafe { libc::getuid() };
+        let root = unsafe { libc::geteuid() } == 0;
+        let expected = format!(
+            "{}{}",
+            get_user_name_by_uid(uid).unwrap_or_default(),
+            if root { '#' } else { '$' }
+        );
+        assert_eq!(render("\\u\\$", &shell), expected);
+    }
+
+    #[test]
+    fn prompts_default_like_bash() {
+        let shell = Shell::default();
+        assert_eq!(primary(&shell), "$ ");
+        assert_eq!(secondary(&shell), "> ");
+        let shell = shell_with(&[("PS1", "\\?> "), ("PS2", "... ")]);
+        assert_eq!(primary(&shell), "0> ");
+        assert_eq!(secondary(&shell), "... ");
+    }
+}
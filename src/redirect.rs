@@ -0,0 +1,319 @@
+use crate::parser::{Redirect, RedirectKind};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+
+// A writer for a closed descriptor: every write fails, the way writing to
+// a closed fd does.
+struct ClosedFd;
+
+impl Write for ClosedFd {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EBADF))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Feeds `text` to a command through a pipe. The writing happens on its own
+// thread so text bigger than the pipe's buffer can't block the shell before
+// the command has even started reading.
+fn pipe_text(text: String) -> Result<File, String> {
+    let (reader, mut writer) = io::pipe().map_err(|e| format!("pipe: {e}"))?;
+    thread::spawn(move || {
+        let _ = writer.write_all(text.as_bytes());
+    });
+    Ok(File::from(OwnedFd::from(reader)))
+}
+
+fn open_file(filename: &str, options: &OpenOptions) -> Result<File, String> {
+    options
+        .open(filename)
+        .map_err(|e| format!("{filename}: {e}"))
+}
+
+// The file descriptors a command runs with, keyed by number. It starts out
+// as copies of the shell's own stdin/stdout/stderr, gets the pipeline's
+// pipes put in place, and then has the command's redirections applied in
+// order. A descriptor with no entry is closed.
+pub struct FdTable {
+    fds: BTreeMap<i32, File>,
+}
+
+impl FdTable {
+    pub fn inherited() -> FdTable {
+        let mut fds = BTreeMap::new();
+        let std_fds = [
+            io::stdin().as_fd().try_clone_to_owned(),
+            io::stdout().as_fd().try_clone_to_owned(),
+            io::stderr().as_fd().try_clone_to_owned(),
+        ];
+        for (fd, owned) in (0..).zip(std_fds) {
+            if let Ok(owned) = owned {
+                fds.insert(fd, File::from(owned));
+            }
+        }
+        FdTable { fds }
+    }
+
+    // A copy of the table with every descriptor duplicated, for a command
+    // to start out from.
+    pub fn try_clone(&self) -> io::Result<FdTable> {
+        let mut fds = BTreeMap::new();
+        for (&fd, file) in &self.fds {
+            fds.insert(fd, file.try_clone()?);
+        }
+        Ok(FdTable { fds })
+    }
+
+    pub fn set(&mut self, fd: i32, file: impl Into<OwnedFd>) {
+        self.fds.insert(fd, File::from(file.into()));
+    }
+
+    // Applies one redirection, opening whatever file it names.
+    pub fn apply(&mut self, redirect: &Redirect) -> Result<(), String> {
+        let file = match &redirect.kind {
+            RedirectKind::Overwrite(filename) => open_file(
+                filename,
+                OpenOptions::new().write(true).create(true).truncate(true),
+            )?,
+            RedirectKind::Append(filename) => {
+                open_file(filename, OpenOptions::new().create(true).append(true))?
+            }
+            RedirectKind::Input(filename) => open_file(filename, OpenOptions::new().read(true))?,
+            RedirectKind::HereString(word) => pipe_text(format!("{word}\n"))?,
+            RedirectKind::HereDoc(heredoc) => pipe_text(heredoc.body.clone())?,
+            RedirectKind::Dup(target) => self
+                .fds
+                .get(target)
+                .ok_or_else(|| format!("{target}: Bad file descriptor"))?
+                .try_clone()
+                .map_err(|e| format!("{target}: {e}"))?,
+            RedirectKind::Close => {
+                self.fds.remove(&redirect.fd);
+                return Ok(());
+            }
+        };
+        self.fds.insert(redirect.fd, file);
+        Ok(())
+    }
+
+    pub fn apply_all<'a>(
+        &mut self,
+        redirects: impl IntoIterator<Item = &'a Redirect>,
+    ) -> Result<(), String> {
+        redirects
+            .into_iter()
+            .try_for_each(|redirect| self.apply(redirect))
+    }
+
+    // Writes a message to wherever stderr currently points, so errors from
+    // e.g. a failed redirection respect any `2>` applied before it.
+    pub fn report(&self, message: &str) {
+        if let Some(mut stderr) = self.fds.get(&2) {
+            let _ = writeln!(stderr, "{message}");
+        }
+    }
+
+    // The streams a built-in works with: stdin (if open) and writers for
+    // stdout and stderr. Stdout is line-buffered like the real one; the
+    // caller should flush it once the built-in finishes.
+    pub fn builtin_streams(&self) -> (Option<File>, Box<dyn Write + Send>, Box<dyn Write + Send>) {
+        let clone = |fd| self.fds.get(&fd).and_then(|f| f.try_clone().ok());
+        let input = clone(0);
+        let output: Box<dyn Write + Send> = match clone(1) {
+            Some(file) => Box::new(LineWriter::new(file)),
+            None => Box::new(ClosedFd),
+        };
+        let error: Box<dyn Write + Send> = match clone(2) {
+            Some(file) => Box::new(file),
+            None => Box::new(ClosedFd),
+        };
+        (input, output, error)
+    }
+
+    // Sets `command` up to run with exactly these descriptors: 0-2 become
+    // its stdio, higher ones are moved into place just before exec, and any
+    // of 0-9 missing from the table are closed in the child.
+    pub fn configure(&self, command: &mut Command) -> io::Result<()> {
+        let stdio = |fd| -> io::Result<Stdio> {
+            Ok(match self.fds.get(&fd) {
+                Some(file) => file.try_clone()?.into(),
+                None => Stdio::inherit(),
+            })
+        };
+        command.stdin(stdio(0)?).stdout(stdio(1)?).stderr(stdio(2)?);
+
+        // Copies of the higher descriptors are first moved to 10 or above,
+        // so that moving one into place in the child can never clobber
+        // another that's still waiting to be moved.
+        let mut moves: Vec<(OwnedFd, i32)> = Vec::new();
+        for (&fd, file) in self.fds.range(3..) {
+            let high = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 10) };
+            if high < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            moves.push((unsafe { OwnedFd::from_raw_fd(high) }, fd));
+        }
+        let closed: Vec<i32> = (0..10).filter(|fd| !self.fds.contains_key(fd)).collect();
+
+        unsafe {
+            command.pre_exec(move || {
+                for (source, fd) in &moves {
+                    if libc::dup2(source.as_raw_fd(), *fd) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                for &fd in &closed {
+                    libc::close(fd);
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::HereDoc;
+    use std::io::{Read, Seek};
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "zero_shell_redirect_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn redirect(fd: i32, kind: RedirectKind) -> Redirect {
+        Redirect { fd, kind }
+    }
+
+    fn read_all(mut file: &File) -> String {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn apply_opens_output_files() {
+        let dir = temp_dir("output");
+        let path = dir.join("out.txt");
+        let filename = path.to_string_lossy().to_string();
+        let mut fds = FdTable::inherited();
+
+        fds.apply(&redirect(1, RedirectKind::Overwrite(filename.clone())))
+            .unwrap();
+        writeln!(&fds.fds[&1], "one").unwrap();
+        fds.apply(&redirect(1, RedirectKind::Append(filename.clone())))
+            .unwrap();
+        writeln!(&fds.fds[&1], "two").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+
+        fds.apply(&redirect(1, RedirectKind::Overwrite(filename)))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn apply_dup_makes_fd_share_target() {
+        let dir = temp_dir("dup");
+        let path = dir.join("both.txt");
+        let mut fds = FdTable::inherited();
+
+        fds.apply_all(&[
+            redirect(
+                1,
+                RedirectKind::Overwrite(path.to_string_lossy().to_string()),
+            ),
+            redirect(2, RedirectKind::Dup(1)),
+        ])
+        .unwrap();
+        write!(&fds.fds[&1], "out ").unwrap();
+        write!(&fds.fds[&2], "err").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out err");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn apply_dup_of_closed_fd_fails() {
+        let mut fds = FdTable::inherited();
+        assert!(fds.apply(&redirect(1, RedirectKind::Dup(7))).is_err());
+    }
+
+    #[test]
+    fn apply_close_removes_fd() {
+        let mut fds = FdTable::inherited();
+        fds.apply(&redirect(1, RedirectKind::Close)).unwrap();
+        let (_, mut output, _) = fds.builtin_streams();
+        assert!(writeln!(output, "lost")
+            .and_then(|()| output.flush())
+            .is_err());
+    }
+
+    #[test]
+    fn apply_reports_missing_input_file() {
+        let mut fds = FdTable::inherited();
+        let result = fds.apply(&redirect(
+            0,
+            RedirectKind::Input("/nonexistent/zero_shell".to_string()),
+        ));
+        assert!(result.unwrap_err().starts_with("/nonexistent/zero_shell: "));
+    }
+
+    #[test]
+    fn apply_feeds_here_strings_and_heredocs() {
+        let mut fds = FdTable::inherited();
+        fds.apply(&redirect(0, RedirectKind::HereString("hi".to_string())))
+            .unwrap();
+        assert_eq!(read_all(&fds.fds[&0]), "hi\n");
+
+        fds.apply(&redirect(
+            0,
+            RedirectKind::HereDoc(HereDoc {
+                delimiter: "EOF".to_string(),
+                strip_tabs: false,
+                expand: false,
+                body: "line one\nline two\n".to_string(),
+            }),
+        ))
+        .unwrap();
+        assert_eq!(read_all(&fds.fds[&0]), "line one\nline two\n");
+    }
+
+    #[test]
+    fn configure_passes_higher_fds_to_child() {
+        let dir = temp_dir("configure");
+        let path = dir.join("three.txt");
+        let mut fds = FdTable::inherited();
+        fds.apply(&redirect(
+            3,
+            RedirectKind::Overwrite(path.to_string_lossy().to_string()),
+        ))
+        .unwrap();
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo via three >&3"]);
+        fds.configure(&mut command).unwrap();
+        assert!(command.status().unwrap().success());
+
+        let mut file = File::open(&path).unwrap();
+        file.rewind().unwrap();
+        assert_eq!(read_all(&file), "via three\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
-use libc::{getgrgid_r, getpwuid_r};
-use std::ffi::CStr;
+use libc::{endpwent, getgrgid_r, getpwent, getpwnam_r, getpwuid_r, setpwent};
+use std::ffi::{CStr, CString};
 use std::mem;
 use std::ptr;
 
@@ -23,6 +23,48 @@ pub fn get_user_name_by_uid(uid: u32) -> Option<String> {
     None
 }
 
+pub fn get_home_dir_by_name(name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    let mut pwd = unsafe { mem::zeroed() };
+    let mut buf = vec![0u8; 1024];
+    let mut result = ptr::null_mut();
+    unsafe {
+        if getpwnam_r(
+            name.as_ptr(),
+            &raw mut pwd,
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+            &raw mut result,
+        ) == 0
+            && !result.is_null()
+        {
+            return Some(CStr::from_ptr(pwd.pw_dir).to_string_lossy().into_owned());
+        }
+    }
+    None
+}
+
+// Every user name in the password database.
+pub fn get_user_names() -> Vec<String> {
+    let mut names = Vec::new();
+    unsafe {
+        setpwent();
+        loop {
+            let entry = getpwent();
+            if entry.is_null() {
+                break;
+            }
+            names.push(
+                CStr::from_ptr((*entry).pw_name)
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        endpwent();
+    }
+    names
+}
+
 pub fn get_group_name_by_gid(gid: u32) -> Option<String> {
     let mut grp = unsafe { mem::zeroed() };
     let mut buf = vec![0u8; 1024];
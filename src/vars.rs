@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::env;
+
+// One shell variable. `value` is None for a name that has been marked
+// (e.g. by `export NAME`) but never given a value, which still counts as
+// unset for expansion.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Variable {
+    value: Option<String>,
+    exported: bool,
+    readonly: bool,
+}
+
+// The shell's variables, kept in name order so listings come out sorted.
+// Exported ones make up the environment external programs are started
+// with; the shell's own process environment is only read once at startup.
+#[derive(Clone, Debug, Default)]
+pub struct Variables {
+    vars: BTreeMap<String, Variable>,
+}
+
+// A valid variable name: a letter or underscore followed by letters,
+// digits and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Splits a `NAME=value` word into its name and value, or returns None if
+// the part before the first `=` isn't a valid name.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_valid_name(name).then_some((name, value))
+}
+
+impl Variables {
+    // Every variable in the shell's environment, already exported.
+    pub fn from_env() -> Variables {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let var = Variable {
+                    value: Some(value),
+                    exported: true,
+                    readonly: false,
+                };
+                (name, var)
+            })
+            .collect();
+        Variables { vars }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    // The names of the variables that are set, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.value.is_some())
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(format!("{name}: readonly variable"));
+        }
+        var.value = Some(value.to_string());
+        Ok(())
+    }
+
+    pub fn export(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().exported = true;
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), String> {
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(format!("{name}: cannot unset: readonly variable"));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    // Puts `name` back the way it is in `saved`, undoing a temporary
+    // assignment.
+    pub fn restore_from(&mut self, saved: &Variables, name: &str) {
+        match saved.vars.get(name) {
+            Some(var) => self.vars.insert(name.to_string(), var.clone()),
+            None => self.vars.remove(name),
+        };
+    }
+
+    // The exported variables that have values, as the environment for an
+    // external program.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().filter_map(|(name, var)| match &var.value {
+            Some(value) if var.exported => Some((name.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
+
+    // The lines `export` (with `exported`) or `readonly` (without) prints
+    // when run with no arguments, in a form that can be pasted back in.
+    pub fn listing(&self, keyword: &str, exported: bool) -> Vec<String> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| if exported { var.exported } else { var.readonly })
+            .map(|(name, var)| match &var.value {
+                Some(value) => format!("{keyword} {name}=\"{}\"", escape_double_quoted(value)),
+                None => format!("{keyword} {name}"),
+            })
+            .collect()
+    }
+}
+
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_valid_name_checks_identifier_rules() {
+        assert!(is_valid_name("PATH"));
+        assert!(is_valid_name("_x1"));
+        assert!(!is_valid_name("1x"));
+        assert!(!is_valid_name("a-b"));
+        assert!(!is_valid_name(""));
+    }
+
+    #[test]
+    fn split_assignment_needs_a_valid_name() {
+        assert_eq!(split_assignment("X=1"), Some(("X", "1")));
+        assert_eq!(split_assignment("X=a=b"), Some(("X", "a=b")));
+        assert_eq!(split_assignment("X="), Some(("X", "")));
+        assert_eq!(split_assignment("=1"), None);
+        assert_eq!(split_assignment("--opt=1"), None);
+        assert_eq!(split_assignment("plain"), None);
+    }
+
+    #[test]
+    fn set_and_unset_round_trip() {
+        let mut vars = Variables::default();
+        assert_eq!(vars.get("X"), None);
+        vars.set("X", "1").unwrap();
+        assert_eq!(vars.get("X"), Some("1"));
+        vars.unset("X").unwrap();
+        assert_eq!(vars.get("X"), None);
+    }
+
+    #[test]
+    fn readonly_variables_refuse_changes() {
+        let mut vars = Variables::default();
+        vars.set("X", "1").unwrap();
+        vars.set_readonly("X");
+        assert_eq!(vars.set("X", "2").unwrap_err(), "X: readonly variable");
+        assert!(vars.unset("X").is_err());
+        assert_eq!(vars.get("X"), Some("1"));
+    }
+
+    #[test]
+    fn exported_skips_unexported_and_valueless_names() {
+        let mut vars = Variables::default();
+        vars.set("LOCAL", "1").unwrap();
+        vars.set("SHARED", "2").unwrap();
+        vars.export("SHARED");
+        vars.export("EMPTY");
+        assert_eq!(vars.exported().collect::<Vec<_>>(), vec![("SHARED", "2")]);
+    }
+
+    #[test]
+    fn listing_quotes_values() {
+        let mut vars = Variables::default();
+        vars.set("A", "say \"hi\" $x").unwrap();
+        vars.export("A");
+        vars.export("B");
+        assert_eq!(
+            vars.listing("export", true),
+            vec!["export A=\"say \\\"hi\\\" \\$x\"", "export B"]
+        );
+        assert!(vars.listing("readonly", false).is_empty());
+    }
+
+    #[test]
+    fn from_env_exports_environment() {
+        let vars = Variables::from_env();
+        let path = env::var("PATH").unwrap();
+        assert_eq!(vars.get("PATH"), Some(path.as_str()));
+        assert!(vars.exported().any(|(name, _)| name == "PATH"));
+    }
+}